@@ -1,8 +1,26 @@
 use macroquad::audio::{PlaySoundParams, Sound, load_sound_from_bytes, play_sound, stop_sound};
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
 
 pub struct AudioManager {
-    beep: Sound,
+    // None when silent
+    beep: Option<Sound>,
     is_playing: bool,
+    // MegaChip sampled sound, kept to stop it, freed when dropped
+    sample: Option<Sound>,
+}
+
+// Load a sound from inside the emulation loop. Sounds load synchronously
+// outside the browser, so the future is ready the first time it's polled
+fn load_sound_now(data: &[u8]) -> Option<Sound> {
+    let mut future = pin!(load_sound_from_bytes(data));
+    match future
+        .as_mut()
+        .poll(&mut Context::from_waker(Waker::noop()))
+    {
+        Poll::Ready(Ok(sound)) => Some(sound),
+        _ => None,
+    }
 }
 
 impl AudioManager {
@@ -12,15 +30,27 @@ impl AudioManager {
             .await
             .expect("Unable to load embedded beep.wav");
         Self {
-            beep,
+            beep: Some(beep),
             is_playing: false,
+            sample: None,
+        }
+    }
+
+    // Audio that plays nothing, for running without a window (e.g. tests)
+    pub fn silent() -> Self {
+        Self {
+            beep: None,
+            is_playing: false,
+            sample: None,
         }
     }
 
     pub fn start_beep(&mut self) {
-        if !self.is_playing {
+        if let Some(beep) = &self.beep
+            && !self.is_playing
+        {
             play_sound(
-                &self.beep,
+                beep,
                 PlaySoundParams {
                     looped: true,
                     volume: 0.2,
@@ -31,7 +61,34 @@ impl AudioManager {
     }
 
     pub fn stop_beep(&mut self) {
-        stop_sound(&self.beep);
+        if let Some(beep) = &self.beep {
+            stop_sound(beep);
+        }
         self.is_playing = false;
     }
+
+    // Play a WAV file in place of the last sample
+    pub fn play_sample(&mut self, wav: &[u8], looped: bool) {
+        self.stop_sample();
+        // Silent audio loads no samples either
+        if self.beep.is_none() {
+            return;
+        }
+        self.sample = load_sound_now(wav);
+        if let Some(sample) = &self.sample {
+            play_sound(
+                sample,
+                PlaySoundParams {
+                    looped,
+                    volume: 0.2,
+                },
+            );
+        }
+    }
+
+    pub fn stop_sample(&mut self) {
+        if let Some(sample) = self.sample.take() {
+            stop_sound(&sample);
+        }
+    }
 }
@@ -0,0 +1,226 @@
+use crate::framebuffer::{Framebuffer, PixelFormat};
+use std::collections::VecDeque;
+
+// Hardware added to the COSMAC VIP by CHIP-8X: the VP-590 color board,
+// a second hex keypad and the I/O port.
+
+// ARGB colors of the VP-590 color board, by color number
+pub const PALETTE: [u32; 8] = [
+    0xFF000000, // 0 black
+    0xFFFF0000, // 1 red
+    0xFF0000FF, // 2 blue
+    0xFFFF00FF, // 3 violet
+    0xFF00FF00, // 4 green
+    0xFFFFFF00, // 5 yellow
+    0xFF00FFFF, // 6 aqua
+    0xFFFFFFFF, // 7 white
+];
+
+// Background colors 02A0 cycles through
+const BACKGROUNDS: [u8; 4] = [2, 0, 4, 1];
+
+// The screen is colored in zones 8 pixels wide and 1 pixel high. BXY0 colors
+// blocks of 4 zone rows at once, BXYN single rows
+pub const ZONE_WIDTH: usize = 8;
+const ZONE_COLS: usize = 64 / ZONE_WIDTH;
+const ZONE_ROWS: usize = 32;
+// Height of the blocks BXY0 colors
+const BLOCK_HEIGHT: usize = 4;
+// Foreground color after reset
+const DEFAULT_FOREGROUND: u8 = 1;
+
+// VP-590 color board state
+#[derive(Clone, Debug)]
+pub struct ColorBoard {
+    // Index into BACKGROUNDS
+    background: usize,
+    // Foreground color number of each zone
+    zones: [u8; ZONE_COLS * ZONE_ROWS],
+}
+
+impl Default for ColorBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ColorBoard {
+    pub fn new() -> Self {
+        Self {
+            background: 0,
+            zones: [DEFAULT_FOREGROUND; ZONE_COLS * ZONE_ROWS],
+        }
+    }
+
+    // 02A0 - Step to the next background color
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+    }
+
+    // BXY0 - Color blocks of 8x4 pixels. The low nibble of horizontal is the
+    // left column and the high nibble the width, likewise for vertical in
+    // rows of blocks
+    pub fn fill_blocks(&mut self, horizontal: u8, vertical: u8, color: u8) {
+        let (col, width) = ((horizontal & 0xF) as usize, (horizontal >> 4) as usize);
+        let (row, height) = ((vertical & 0xF) as usize, (vertical >> 4) as usize);
+        for block_row in row..=row + height {
+            for zone_row in block_row * BLOCK_HEIGHT..(block_row + 1) * BLOCK_HEIGHT {
+                self.fill_row(col, width, zone_row, color);
+            }
+        }
+    }
+
+    // BXYN - Color rows rows of zones from the pixel at (x, y)
+    pub fn fill_rows(&mut self, x: u8, y: u8, rows: u8, color: u8) {
+        let col = x as usize / ZONE_WIDTH;
+        for zone_row in y as usize..y as usize + rows as usize {
+            self.fill_row(col, 0, zone_row, color);
+        }
+    }
+
+    // Color width + 1 zones of a zone row, clipped to the screen
+    fn fill_row(&mut self, col: usize, width: usize, zone_row: usize, color: u8) {
+        if zone_row >= ZONE_ROWS {
+            return;
+        }
+        for zone_col in col..=(col + width).min(ZONE_COLS - 1) {
+            self.zones[zone_col + ZONE_COLS * zone_row] = color & 0x7;
+        }
+    }
+
+    // Return the background color number
+    pub fn get_background(&self) -> u8 {
+        BACKGROUNDS[self.background]
+    }
+
+    // Color a mono screen, giving the color number of each pixel
+    pub fn colorize(&self, screen: &Framebuffer) -> Framebuffer {
+        let (width, height) = (screen.get_width(), screen.get_height());
+        let mut colored = Framebuffer::new(PixelFormat::Indexed8, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if screen.get(x, y) != 0 {
+                    self.get_foreground(x, y)
+                } else {
+                    self.get_background()
+                };
+                colored.set(x, y, color as u32);
+            }
+        }
+        colored
+    }
+
+    // Return the foreground color number of the pixel at (x, y)
+    pub fn get_foreground(&self, x: usize, y: usize) -> u8 {
+        let col = (x / ZONE_WIDTH).min(ZONE_COLS - 1);
+        let row = y.min(ZONE_ROWS - 1);
+        self.zones[col + ZONE_COLS * row]
+    }
+}
+
+// Stand-in for the I/O port, which the host reads and writes in place of a
+// peripheral. FXF8 latches a byte for the host, FXFB waits for one from it
+#[derive(Clone, Debug, Default)]
+pub struct IoPort {
+    output: Option<u8>,
+    input: VecDeque<u8>,
+}
+
+impl IoPort {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Queue a byte for FXFB to read
+    pub fn send(&mut self, value: u8) {
+        self.input.push_back(value);
+    }
+
+    // Return the last byte FXF8 wrote, if any
+    pub fn get_output(&self) -> Option<u8> {
+        self.output
+    }
+
+    // FXF8 - Output a byte
+    pub(crate) fn write(&mut self, value: u8) {
+        self.output = Some(value);
+    }
+
+    // FXFB - Input a byte, None until the host has sent one
+    pub(crate) fn read(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_background_steps_through_the_colors() {
+        let mut board = ColorBoard::new();
+        let mut colors = vec![board.get_background()];
+        for _ in 0..4 {
+            board.cycle_background();
+            colors.push(board.get_background());
+        }
+        assert_eq!(colors, [2, 0, 4, 1, 2]);
+    }
+
+    #[test]
+    fn fill_blocks_colors_8x4_blocks() {
+        let mut board = ColorBoard::new();
+        // Columns 0 - 1, block row 0
+        board.fill_blocks(0x10, 0x00, 5);
+        assert_eq!(board.get_foreground(0, 0), 5);
+        assert_eq!(board.get_foreground(15, 3), 5);
+        assert_eq!(board.get_foreground(16, 0), DEFAULT_FOREGROUND);
+        assert_eq!(board.get_foreground(0, 4), DEFAULT_FOREGROUND);
+
+        // Clipped at the right edge and bottom
+        board.fill_blocks(0x37, 0x17, 6);
+        assert_eq!(board.get_foreground(63, 31), 6);
+        assert_eq!(board.get_foreground(55, 27), DEFAULT_FOREGROUND);
+    }
+
+    #[test]
+    fn fill_rows_colors_single_zone_rows() {
+        let mut board = ColorBoard::new();
+        board.fill_rows(20, 5, 2, 3);
+        assert_eq!(board.get_foreground(16, 5), 3);
+        assert_eq!(board.get_foreground(23, 6), 3);
+        assert_eq!(board.get_foreground(24, 5), DEFAULT_FOREGROUND);
+        assert_eq!(board.get_foreground(16, 7), DEFAULT_FOREGROUND);
+        assert_eq!(board.get_foreground(16, 4), DEFAULT_FOREGROUND);
+    }
+
+    #[test]
+    fn colorize_uses_zone_foreground_and_background() {
+        let mut board = ColorBoard::new();
+        board.fill_rows(0, 0, 1, 4);
+        let mut screen = Framebuffer::new(PixelFormat::Mono, 64, 32);
+        screen.set(0, 0, 1);
+        screen.set(8, 0, 1);
+
+        let colored = board.colorize(&screen);
+        assert_eq!(colored.get_format(), PixelFormat::Indexed8);
+        assert_eq!(colored.get(0, 0), 4);
+        assert_eq!(colored.get(8, 0), DEFAULT_FOREGROUND as u32);
+        assert_eq!(colored.get(1, 0), board.get_background() as u32);
+    }
+
+    #[test]
+    fn io_port_passes_bytes_in_order() {
+        let mut port = IoPort::new();
+        assert_eq!(port.read(), None);
+        port.send(1);
+        port.send(2);
+        assert_eq!(port.read(), Some(1));
+        assert_eq!(port.read(), Some(2));
+        assert_eq!(port.read(), None);
+
+        assert_eq!(port.get_output(), None);
+        port.write(0xAB);
+        assert_eq!(port.get_output(), Some(0xAB));
+    }
+}
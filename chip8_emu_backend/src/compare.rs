@@ -0,0 +1,416 @@
+use crate::error::ParseError;
+use crate::{Cpu, Registers};
+use std::fmt;
+
+// CPU state before one instruction, as recorded by a reference emulator.
+// Fields the reference didn't record are None and aren't compared.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RefState {
+    // Line of the trace file this state came from
+    pub line: usize,
+    pub pc: u16,
+    pub opcode: Option<u16>,
+    pub v_reg: [Option<u8>; 16],
+    pub i_reg: Option<u32>,
+    pub sp: Option<u16>,
+    pub delay_t: Option<u8>,
+    pub sound_t: Option<u8>,
+}
+
+// A trace produced by another emulator.
+// Uses the same key=value fields as our own trace files (see trace.rs):
+// PC is required, OP, V (all 16 registers as 32 hex digits), V0-VF, I, SP,
+// DT and ST are optional. Keys are case insensitive, values are hex with an
+// optional 0x prefix, anything after a '|' or '#' and unknown keys are ignored.
+pub struct ReferenceTrace {
+    states: Vec<RefState>,
+}
+
+impl ReferenceTrace {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut states = Vec::new();
+
+        for (idx, raw_line) in text.lines().enumerate() {
+            let line = idx + 1;
+            let content = raw_line.split(['|', '#']).next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            let err = |message: String| ParseError { line, message };
+            let mut state = RefState {
+                line,
+                ..Default::default()
+            };
+            let mut has_pc = false;
+
+            for token in content.split_whitespace() {
+                let Some((key, value)) = token.split_once(['=', ':']) else {
+                    continue;
+                };
+                let key = key.to_ascii_uppercase();
+                let value = value.trim_start_matches("0x").trim_start_matches("0X");
+                let hex = |max: u32| {
+                    u32::from_str_radix(value, 16)
+                        .ok()
+                        .filter(|v| *v <= max)
+                        .ok_or_else(|| err(format!("invalid value for {}: {}", key, value)))
+                };
+
+                match key.as_str() {
+                    "PC" => {
+                        state.pc = hex(0xFFFF)? as u16;
+                        has_pc = true;
+                    }
+                    "OP" => state.opcode = Some(hex(0xFFFF)? as u16),
+                    "I" => state.i_reg = Some(hex(0xFFFFFF)?),
+                    "SP" => state.sp = Some(hex(0xFFFF)? as u16),
+                    "DT" => state.delay_t = Some(hex(0xFF)? as u8),
+                    "ST" => state.sound_t = Some(hex(0xFF)? as u8),
+                    "V" => {
+                        if value.len() != 32 || !value.is_ascii() {
+                            return Err(err(format!("V needs 32 hex digits, got {}", value)));
+                        }
+                        for (reg, idx) in state.v_reg.iter_mut().zip((0..32).step_by(2)) {
+                            let byte = u8::from_str_radix(&value[idx..idx + 2], 16)
+                                .map_err(|_| err(format!("invalid V registers: {}", value)))?;
+                            *reg = Some(byte);
+                        }
+                    }
+                    _ => {
+                        // V0 - VF
+                        if let Some(reg) = key.strip_prefix('V')
+                            && reg.len() == 1
+                            && let Ok(reg) = usize::from_str_radix(reg, 16)
+                        {
+                            state.v_reg[reg] = Some(hex(0xFF)? as u8);
+                        }
+                    }
+                }
+            }
+
+            if !has_pc {
+                return Err(err("missing PC".to_string()));
+            }
+            states.push(state);
+        }
+
+        Ok(Self { states })
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+// First instruction where the CPU disagreed with the reference trace
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    // Number of instructions that matched before this one
+    pub step: usize,
+    pub expected: RefState,
+    pub actual: Registers,
+    pub actual_opcode: u16,
+    // One entry per mismatched field
+    pub diffs: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Diverged at step {} (trace line {}), PC {:04X}:",
+            self.step, self.expected.line, self.actual.pc
+        )?;
+        for diff in &self.diffs {
+            writeln!(f, "  {}", diff)?;
+        }
+        Ok(())
+    }
+}
+
+// Compare recorded fields against the CPU, one line per mismatch
+fn diff_state(expected: &RefState, actual: &Registers, actual_opcode: u16) -> Vec<String> {
+    let mut diffs = Vec::new();
+
+    if expected.pc != actual.pc {
+        diffs.push(format!(
+            "PC: expected {:04X}, got {:04X}",
+            expected.pc, actual.pc
+        ));
+    }
+    if let Some(op) = expected.opcode
+        && op != actual_opcode
+    {
+        diffs.push(format!(
+            "OP: expected {:04X}, got {:04X}",
+            op, actual_opcode
+        ));
+    }
+    for (idx, (expected, actual)) in expected.v_reg.iter().zip(actual.v_reg).enumerate() {
+        if let Some(expected) = *expected
+            && expected != actual
+        {
+            diffs.push(format!(
+                "V{:X}: expected {:02X}, got {:02X}",
+                idx, expected, actual
+            ));
+        }
+    }
+    if let Some(i_reg) = expected.i_reg
+        && i_reg != actual.i_reg
+    {
+        diffs.push(format!(
+            "I: expected {:04X}, got {:04X}",
+            i_reg, actual.i_reg
+        ));
+    }
+    if let Some(sp) = expected.sp
+        && sp != actual.sp
+    {
+        diffs.push(format!("SP: expected {:X}, got {:X}", sp, actual.sp));
+    }
+    if let Some(delay_t) = expected.delay_t
+        && delay_t != actual.delay_t
+    {
+        diffs.push(format!(
+            "DT: expected {:02X}, got {:02X}",
+            delay_t, actual.delay_t
+        ));
+    }
+    if let Some(sound_t) = expected.sound_t
+        && sound_t != actual.sound_t
+    {
+        diffs.push(format!(
+            "ST: expected {:02X}, got {:02X}",
+            sound_t, actual.sound_t
+        ));
+    }
+
+    diffs
+}
+
+// Runs the CPU in lockstep with a reference trace
+pub struct Lockstep {
+    reference: ReferenceTrace,
+    // Index of the next reference state to compare against
+    position: usize,
+}
+
+impl Lockstep {
+    pub fn new(reference: ReferenceTrace) -> Self {
+        Self {
+            reference,
+            position: 0,
+        }
+    }
+
+    // Number of instructions checked so far
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.reference.len()
+    }
+
+    // Check the CPU against the next reference state and, if it matches,
+    // execute one instruction. Does nothing once the trace is exhausted.
+    // While a draw waits for vblank the CPU only ticks, the state after the
+    // draw is compared once it has run.
+    pub fn step(&mut self, cpu: &mut Cpu) -> Result<(), Box<Divergence>> {
+        let Some(expected) = self.reference.states.get(self.position) else {
+            return Ok(());
+        };
+        if cpu.is_waiting_for_vblank() {
+            cpu.tick();
+            return Ok(());
+        }
+
+        let actual = cpu.get_registers();
+        let actual_opcode = cpu.peek_opcode();
+        let diffs = diff_state(expected, &actual, actual_opcode);
+        if !diffs.is_empty() {
+            return Err(Box::new(Divergence {
+                step: self.position,
+                expected: *expected,
+                actual,
+                actual_opcode,
+                diffs,
+            }));
+        }
+
+        self.position += 1;
+        cpu.tick();
+        Ok(())
+    }
+
+    // Run the whole trace without a frontend, decrementing timers every
+    // ticks_per_frame instructions like the game loop does, or as soon as a
+    // draw waits for vblank
+    pub fn run(&mut self, cpu: &mut Cpu, ticks_per_frame: usize) -> Result<(), Box<Divergence>> {
+        let mut frame_ticks = 0;
+        while !self.is_finished() {
+            self.step(cpu)?;
+            frame_ticks += 1;
+            if frame_ticks == ticks_per_frame || cpu.is_waiting_for_vblank() {
+                cpu.tick_timers();
+                frame_ticks = 0;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioManager, Chip8Variant};
+
+    // 6005 7001 1204: V0 = 5, V0 += 1, then jump to itself forever
+    const ROM: [u8; 6] = [0x60, 0x05, 0x70, 0x01, 0x12, 0x04];
+
+    fn cpu_with_rom() -> Cpu {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        cpu.load(&ROM).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn parse_reads_fields_and_skips_comments() {
+        let trace = ReferenceTrace::parse(
+            "# recorded by another emulator\n\
+             \n\
+             PC=0200 OP=6005 V0=00 I=0 SP=0 DT=0 ST=0\n\
+             pc:0x202 v=05000000000000000000000000000000 | V0=05\n",
+        )
+        .unwrap();
+        assert_eq!(trace.len(), 2);
+
+        let first = trace.states[0];
+        assert_eq!(first.line, 3);
+        assert_eq!(first.pc, 0x200);
+        assert_eq!(first.opcode, Some(0x6005));
+        assert_eq!(first.v_reg[0], Some(0));
+        assert_eq!(first.v_reg[1], None);
+        assert_eq!(first.i_reg, Some(0));
+        assert_eq!(first.sp, Some(0));
+
+        let second = trace.states[1];
+        assert_eq!(second.line, 4);
+        assert_eq!(second.pc, 0x202);
+        assert_eq!(second.opcode, None);
+        let mut v_reg = [Some(0); 16];
+        v_reg[0] = Some(5);
+        assert_eq!(second.v_reg, v_reg);
+        assert_eq!(second.delay_t, None);
+    }
+
+    #[test]
+    fn parse_reports_line_of_errors() {
+        let missing_pc = ReferenceTrace::parse("PC=200\nV0=01\n").err().unwrap();
+        assert_eq!(missing_pc.line, 2);
+        assert_eq!(missing_pc.message, "missing PC");
+
+        let too_large = ReferenceTrace::parse("PC=200 V3=100").err().unwrap();
+        assert_eq!(too_large.line, 1);
+
+        let short_v = ReferenceTrace::parse("PC=200 V=0102").err().unwrap();
+        assert_eq!(short_v.line, 1);
+    }
+
+    #[test]
+    fn step_runs_a_matching_trace() {
+        let trace = ReferenceTrace::parse(
+            "PC=200 OP=6005 V0=00\n\
+             PC=202 OP=7001 V0=05\n\
+             PC=204 OP=1204 V0=06 I=0\n",
+        )
+        .unwrap();
+        let mut cpu = cpu_with_rom();
+        let mut lock = Lockstep::new(trace);
+
+        for position in 1..=3 {
+            lock.step(&mut cpu).unwrap();
+            assert_eq!(lock.position(), position);
+        }
+        assert!(lock.is_finished());
+        assert_eq!(cpu.get_registers().pc, 0x204);
+
+        // Nothing left to compare, the CPU is left alone
+        lock.step(&mut cpu).unwrap();
+        assert_eq!(lock.position(), 3);
+    }
+
+    #[test]
+    fn step_stops_at_first_divergence() {
+        let trace = ReferenceTrace::parse(
+            "PC=200 OP=6005\n\
+             PC=202 V0=07 DT=01\n\
+             PC=204\n",
+        )
+        .unwrap();
+        let mut cpu = cpu_with_rom();
+        let mut lock = Lockstep::new(trace);
+
+        lock.step(&mut cpu).unwrap();
+        let divergence = lock.step(&mut cpu).unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.expected.line, 2);
+        assert_eq!(divergence.actual.pc, 0x202);
+        assert_eq!(divergence.actual_opcode, 0x7001);
+        assert_eq!(
+            divergence.diffs,
+            ["V0: expected 07, got 05", "DT: expected 01, got 00"]
+        );
+
+        // The diverging instruction isn't executed
+        assert_eq!(lock.position(), 1);
+        assert_eq!(cpu.get_registers().pc, 0x202);
+    }
+
+    // 6005 F015 A000 D001 1208: DT = 5, then draw with the display_wait
+    // quirk, which waits for vblank and so one timer decrement
+    const DRAW_ROM: [u8; 10] = [0x60, 0x05, 0xF0, 0x15, 0xA0, 0x00, 0xD0, 0x01, 0x12, 0x08];
+    const DRAW_TRACE: &str = "PC=200\n\
+                              PC=202 DT=00\n\
+                              PC=204 DT=05\n\
+                              PC=206 OP=D001 DT=05\n\
+                              PC=208 DT=04\n";
+
+    #[test]
+    fn step_waits_for_vblank_before_comparing_a_draw() {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        cpu.load(&DRAW_ROM).unwrap();
+        let mut lock = Lockstep::new(ReferenceTrace::parse(DRAW_TRACE).unwrap());
+
+        for _ in 0..4 {
+            lock.step(&mut cpu).unwrap();
+        }
+        assert!(cpu.is_waiting_for_vblank());
+        for _ in 0..3 {
+            lock.step(&mut cpu).unwrap();
+        }
+        assert_eq!(lock.position(), 4);
+        assert_eq!(cpu.get_registers().pc, 0x206);
+
+        cpu.tick_timers();
+        lock.step(&mut cpu).unwrap();
+        assert_eq!(cpu.get_registers().pc, 0x208);
+        lock.step(&mut cpu).unwrap();
+        assert!(lock.is_finished());
+    }
+
+    #[test]
+    fn run_ends_frames_on_display_wait() {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        cpu.load(&DRAW_ROM).unwrap();
+        let mut lock = Lockstep::new(ReferenceTrace::parse(DRAW_TRACE).unwrap());
+        lock.run(&mut cpu, 100).unwrap();
+        assert!(lock.is_finished());
+    }
+}
@@ -1,8 +1,95 @@
+use std::fmt;
+
 // Enumerable containing Chip 8 Variants
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Chip8Variant {
     Chip8,
-    SuperChip,
+    // Two-page HIRES CHIP-8 for the COSMAC VIP, 64x64 starting at 0x2C0
+    HiresChip8,
+    // CHIP-8X for the COSMAC VIP with the VP-590 color board, starting at 0x300
+    Chip8X,
+    // CHIP-48 for the HP-48
+    Chip48,
+    // SUPER-CHIP 1.0 for the HP-48
+    SuperChip10,
+    // SUPER-CHIP 1.1 for the HP-48
+    SuperChip11,
+    // SUPER-CHIP as run by modern interpreters like Octo
+    SuperChipModern,
+    // MegaChip, SUPER-CHIP with a 256x192 color mode
+    MegaChip,
+}
+
+impl Chip8Variant {
+    // Whether the variant has the SUPER-CHIP instructions and HiRes mode
+    pub fn is_superchip(&self) -> bool {
+        matches!(
+            self,
+            Chip8Variant::SuperChip10
+                | Chip8Variant::SuperChip11
+                | Chip8Variant::SuperChipModern
+                | Chip8Variant::MegaChip
+        )
+    }
+
+    // Whether the variant has the scroll instructions 00CN, 00FB and 00FC,
+    // which were only added in SUPER-CHIP 1.1
+    pub fn can_scroll(&self) -> bool {
+        self.is_superchip() && *self != Chip8Variant::SuperChip10
+    }
+
+    // Variant from its name in lowercase (e.g. "superchip11"), as written
+    // in the ROM database
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "chip8" => Some(Chip8Variant::Chip8),
+            "hireschip8" => Some(Chip8Variant::HiresChip8),
+            "chip8x" => Some(Chip8Variant::Chip8X),
+            "chip48" => Some(Chip8Variant::Chip48),
+            "superchip10" => Some(Chip8Variant::SuperChip10),
+            "superchip11" => Some(Chip8Variant::SuperChip11),
+            "superchipmodern" => Some(Chip8Variant::SuperChipModern),
+            "megachip" => Some(Chip8Variant::MegaChip),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Chip8Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Variant::Chip8 => write!(f, "Chip-8"),
+            Chip8Variant::HiresChip8 => write!(f, "HIRES Chip-8"),
+            Chip8Variant::Chip8X => write!(f, "Chip-8X"),
+            Chip8Variant::Chip48 => write!(f, "Chip-48"),
+            Chip8Variant::SuperChip10 => write!(f, "SuperChip 1.0"),
+            Chip8Variant::SuperChip11 => write!(f, "SuperChip 1.1"),
+            Chip8Variant::SuperChipModern => write!(f, "SuperChip (modern)"),
+            Chip8Variant::MegaChip => write!(f, "MegaChip"),
+        }
+    }
+}
+
+// Enumerable containing how FX55 and FX65 leave I
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryIncrement {
+    // I is left pointing past the last register, I += X + 1
+    XPlusOne,
+    // I is left pointing at the last register, I += X
+    X,
+    // I is unchanged
+    Unchanged,
+}
+
+// Enumerable containing what DXY0 draws in LoRes
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoResDxy0 {
+    // Nothing, it's a sprite of 0 rows
+    Nothing,
+    // An 8x16 sprite
+    Sprite8x16,
+    // A 16x16 sprite, like in HiRes
+    Sprite16x16,
 }
 
 // Quirks struct that contains all the differences in instructions
@@ -11,43 +98,222 @@ pub struct Quirks {
     // 8XY1, 8XY2, 8XY3 Quirk
     pub vf_reset: bool,
     // FX55, FX65 Quirk
-    pub memory: bool,
+    pub memory: MemoryIncrement,
     // 8XY6, 8XYE Quirk
     pub shifting: bool,
     // BNNN Quirk
     pub jumping: bool,
+    // DXYN Quirk, wait for the vertical blank (next tick_timers) to draw
+    pub display_wait: bool,
+    // DXYN Quirk, clip sprites at the screen edges instead of wrapping them
+    pub clipping: bool,
+    // DXYN Quirk, in HiRes VF counts the rows that collided or were clipped
+    // off the bottom instead of being 0 or 1
+    pub collision_rows: bool,
+    // DXY0 Quirk
+    pub lores_dxy0: LoResDxy0,
+    // 00CN, 00FB, 00FC Quirk, in LoRes scroll by HiRes pixels (half as far)
+    pub lores_half_scroll: bool,
+    // 00FE, 00FF Quirk, clear the screen when switching modes instead of
+    // keeping its contents
+    pub mode_switch_clear: bool,
+    // 00FE, 00FF, DXYN Quirk, keep a single 128x64 screen and draw LoRes
+    // pixels as 2x2 blocks on it, so LoRes can scroll by half pixels
+    pub lores_doubling: bool,
 }
 
 impl Quirks {
     pub fn new_variant(variant: Chip8Variant) -> Self {
         match variant {
-            Chip8Variant::Chip8 => Self {
+            Chip8Variant::Chip8 | Chip8Variant::HiresChip8 | Chip8Variant::Chip8X => Self {
                 vf_reset: true,
-                memory: true,
+                memory: MemoryIncrement::XPlusOne,
                 shifting: false,
                 jumping: false,
+                display_wait: true,
+                clipping: true,
+                collision_rows: false,
+                lores_dxy0: LoResDxy0::Nothing,
+                lores_half_scroll: false,
+                mode_switch_clear: false,
+                lores_doubling: false,
+            },
+            Chip8Variant::Chip48 => Self {
+                vf_reset: false,
+                memory: MemoryIncrement::X,
+                shifting: true,
+                jumping: true,
+                display_wait: false,
+                clipping: true,
+                collision_rows: false,
+                lores_dxy0: LoResDxy0::Nothing,
+                lores_half_scroll: false,
+                mode_switch_clear: false,
+                lores_doubling: false,
+            },
+            Chip8Variant::SuperChip10 => Self {
+                vf_reset: false,
+                memory: MemoryIncrement::X,
+                shifting: true,
+                jumping: true,
+                display_wait: false,
+                clipping: true,
+                collision_rows: false,
+                lores_dxy0: LoResDxy0::Sprite8x16,
+                lores_half_scroll: false,
+                mode_switch_clear: false,
+                lores_doubling: true,
             },
-            Chip8Variant::SuperChip => Self {
+            Chip8Variant::SuperChip11 | Chip8Variant::MegaChip => Self {
                 vf_reset: false,
-                memory: false,
+                memory: MemoryIncrement::Unchanged,
                 shifting: true,
                 jumping: true,
+                display_wait: false,
+                clipping: true,
+                collision_rows: true,
+                lores_dxy0: LoResDxy0::Sprite16x16,
+                lores_half_scroll: true,
+                mode_switch_clear: false,
+                lores_doubling: true,
+            },
+            Chip8Variant::SuperChipModern => Self {
+                vf_reset: false,
+                memory: MemoryIncrement::Unchanged,
+                shifting: true,
+                jumping: true,
+                display_wait: false,
+                clipping: true,
+                collision_rows: false,
+                lores_dxy0: LoResDxy0::Sprite16x16,
+                lores_half_scroll: false,
+                mode_switch_clear: true,
+                lores_doubling: false,
             },
         }
     }
 }
 
+// Enumerable containing what happens when a call overflows the stack
+// or a return underflows it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StackPolicy {
+    // Halt the CPU with an error
+    Error,
+    // Break into the debugger before the offending instruction
+    Break,
+    // Wrap the stack pointer around, like interpreters with a circular stack
+    Wrap,
+}
+
 // Enumerable containing resolution modes
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DisplayMode {
     LoRes,
     HiRes,
+    // 256x192 ARGB, see megachip.rs
+    MegaChip,
 }
 
+// Rate the timers count down and the screen refreshes at
+pub const FRAME_RATE: u32 = 60;
+
 // Set the ticks per frame based on Chip 8 Variant
 pub fn ticks_per_frame(variant: Chip8Variant) -> usize {
     match variant {
-        Chip8Variant::SuperChip => 16,
+        // MegaChip ROMs were written for much faster interpreters
+        Chip8Variant::MegaChip => 50,
+        _ if variant.is_superchip() => 16,
         _ => 8,
     }
 }
+
+// CPU speed in instructions per second
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Ips {
+    Limited(u32),
+    // As many instructions as the host can run
+    Unlimited,
+    // Each instruction takes as long as on a COSMAC VIP (see timing.rs)
+    CosmacVip,
+}
+
+impl Ips {
+    // Speed the variant runs at unless a ROM asks for another
+    pub fn default_for(variant: Chip8Variant) -> Self {
+        Ips::Limited(ticks_per_frame(variant) as u32 * FRAME_RATE)
+    }
+
+    // "unlimited", "vip" or a positive number of instructions per second
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text.eq_ignore_ascii_case("unlimited") {
+            return Some(Ips::Unlimited);
+        }
+        if text.eq_ignore_ascii_case("vip") {
+            return Some(Ips::CosmacVip);
+        }
+        text.parse().ok().filter(|ips| *ips > 0).map(Ips::Limited)
+    }
+}
+
+impl fmt::Display for Ips {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Ips::Limited(ips) => write!(f, "{} IPS", ips),
+            Ips::Unlimited => write!(f, "unlimited IPS"),
+            Ips::CosmacVip => write!(f, "COSMAC VIP timing"),
+        }
+    }
+}
+
+// Size of the memory PC and 12-bit addresses reach based on Chip 8 Variant,
+// which is what the debugging tools show
+pub fn address_space(variant: Chip8Variant) -> usize {
+    match variant {
+        Chip8Variant::MegaChip => 0x10000,
+        Chip8Variant::Chip8
+        | Chip8Variant::HiresChip8
+        | Chip8Variant::Chip8X
+        | Chip8Variant::Chip48
+        | Chip8Variant::SuperChip10
+        | Chip8Variant::SuperChip11
+        | Chip8Variant::SuperChipModern => 4096,
+    }
+}
+
+// Size of the whole memory based on Chip 8 Variant. MegaChip's 24-bit I
+// reaches 16 MB for graphics and sound
+pub fn memory_size(variant: Chip8Variant) -> usize {
+    match variant {
+        Chip8Variant::MegaChip => 0x1000000,
+        _ => address_space(variant),
+    }
+}
+
+// Address ROMs are loaded at based on Chip 8 Variant. The CHIP-8X
+// interpreter takes up 0x200 - 0x2FF as well
+pub fn load_address(variant: Chip8Variant) -> u16 {
+    match variant {
+        Chip8Variant::Chip8X => 0x300,
+        _ => 0x200,
+    }
+}
+
+// Address execution starts at based on Chip 8 Variant. HIRES CHIP-8 ROMs
+// start with the interpreter's patch, and the program proper at 0x2C0
+pub fn start_address(variant: Chip8Variant) -> u16 {
+    match variant {
+        Chip8Variant::HiresChip8 => 0x2C0,
+        Chip8Variant::Chip8X => 0x300,
+        _ => 0x200,
+    }
+}
+
+// Size of the LoRes screen based on Chip 8 Variant
+pub fn lores_size(variant: Chip8Variant) -> (usize, usize) {
+    match variant {
+        Chip8Variant::HiresChip8 => (64, 64),
+        _ => (64, 32),
+    }
+}
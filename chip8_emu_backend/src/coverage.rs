@@ -0,0 +1,259 @@
+use crate::config::Chip8Variant;
+use crate::disasm::disassemble;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// How often a conditional skip went each way
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    // Next instruction was skipped
+    pub taken: u64,
+    // Next instruction ran
+    pub not_taken: u64,
+}
+
+// Source location of an instruction, as (file, 1-based line)
+pub type SourceLine = (String, u32);
+
+// Records which addresses executed and which way each skip instruction went
+pub struct Coverage {
+    counts: Vec<u64>,
+    branches: BTreeMap<u16, BranchStats>,
+}
+
+// 3XNN, 4XNN, 5XY0, 9XY0, EX9E and EXA1 conditionally skip the next instruction
+fn is_skip(op: u16) -> bool {
+    matches!(op & 0xF000, 0x3000 | 0x4000)
+        || matches!(op & 0xF00F, 0x5000 | 0x9000)
+        || matches!(op & 0xF0FF, 0xE09E | 0xE0A1)
+}
+
+// Big endian opcode at addr, reading zeroes past the end of memory
+fn opcode_at(ram: &[u8], addr: u16) -> u16 {
+    let addr = addr as usize;
+    let high = ram.get(addr).copied().unwrap_or(0) as u16;
+    let low = ram.get(addr + 1).copied().unwrap_or(0) as u16;
+    high << 8 | low
+}
+
+impl Coverage {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            counts: vec![0; ram_size],
+            branches: BTreeMap::new(),
+        }
+    }
+
+    // Times the instruction at each address executed
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn branches(&self) -> &BTreeMap<u16, BranchStats> {
+        &self.branches
+    }
+
+    // Percentage of the instruction addresses in start..=end that executed
+    pub fn percent(&self, start: u16, end: u16) -> f64 {
+        let addrs = self.instruction_addrs(start, end);
+        let hit = addrs
+            .iter()
+            .filter(|addr| self.counts[**addr as usize] > 0)
+            .count();
+        hit as f64 * 100.0 / addrs.len().max(1) as f64
+    }
+
+    // Record an executed instruction, next_pc is PC after it ran
+    pub(crate) fn record(&mut self, addr: u16, op: u16, next_pc: u16) {
+        if let Some(count) = self.counts.get_mut(addr as usize) {
+            *count += 1;
+        }
+        if is_skip(op) {
+            let stats = self.branches.entry(addr).or_default();
+            if next_pc == addr.wrapping_add(4) {
+                stats.taken += 1;
+            } else {
+                stats.not_taken += 1;
+            }
+        }
+    }
+
+    // Addresses in start..=end treated as instructions: every even address
+    // plus any odd address that actually executed
+    pub fn instruction_addrs(&self, start: u16, end: u16) -> Vec<u16> {
+        let end = end.min(self.counts.len().saturating_sub(1) as u16);
+        (start..=end)
+            .filter(|addr| addr % 2 == start % 2 || self.counts[*addr as usize] > 0)
+            .collect()
+    }
+
+    // Disassembly of the instructions in start..=end, one per line, used as
+    // the source file for reports when no symbols are available
+    pub fn listing(&self, ram: &[u8], variant: Chip8Variant, start: u16, end: u16) -> String {
+        let mut listing = String::new();
+        for addr in self.instruction_addrs(start, end) {
+            let op = opcode_at(ram, addr);
+            let text = disassemble(op, variant);
+            let _ = writeln!(listing, "{:04X}: {:04X}  {}", addr, op, text);
+        }
+        listing
+    }
+
+    // lcov tracefile for the instructions in start..=end. locate maps an
+    // address to its source line; addresses it returns None for are left out.
+    pub fn lcov(
+        &self,
+        test_name: &str,
+        ram: &[u8],
+        start: u16,
+        end: u16,
+        locate: impl Fn(u16) -> Option<SourceLine>,
+    ) -> String {
+        // file -> line -> (execution count, branch stats)
+        let mut files: BTreeMap<String, BTreeMap<u32, (u64, Option<BranchStats>)>> =
+            BTreeMap::new();
+
+        for addr in self.instruction_addrs(start, end) {
+            let Some((file, line)) = locate(addr) else {
+                continue;
+            };
+            let entry = files.entry(file).or_default().entry(line).or_default();
+            entry.0 += self.counts[addr as usize];
+            // Skips that never ran still count as two unreached branches
+            if is_skip(opcode_at(ram, addr)) || self.branches.contains_key(&addr) {
+                let stats = self.branches.get(&addr).copied().unwrap_or_default();
+                let branch = entry.1.get_or_insert_with(BranchStats::default);
+                branch.taken += stats.taken;
+                branch.not_taken += stats.not_taken;
+            }
+        }
+
+        let mut lcov = String::new();
+        let _ = writeln!(lcov, "TN:{}", test_name);
+        for (file, lines) in files {
+            let _ = writeln!(lcov, "SF:{}", file);
+
+            let (mut branches_found, mut branches_hit) = (0, 0);
+            for (line, (count, branch)) in &lines {
+                let Some(branch) = branch else {
+                    continue;
+                };
+                // Never executed skips are reported as '-' for both outcomes
+                for (idx, taken) in [branch.taken, branch.not_taken].iter().enumerate() {
+                    let taken = if *count == 0 {
+                        "-".to_string()
+                    } else {
+                        taken.to_string()
+                    };
+                    let _ = writeln!(lcov, "BRDA:{},0,{},{}", line, idx, taken);
+                }
+                branches_found += 2;
+                branches_hit += (branch.taken > 0) as u32 + (branch.not_taken > 0) as u32;
+            }
+            let _ = writeln!(lcov, "BRF:{}", branches_found);
+            let _ = writeln!(lcov, "BRH:{}", branches_hit);
+
+            for (line, (count, _)) in &lines {
+                let _ = writeln!(lcov, "DA:{},{}", line, count);
+            }
+            let lines_hit = lines.values().filter(|(count, _)| *count > 0).count();
+            let _ = writeln!(lcov, "LF:{}", lines.len());
+            let _ = writeln!(lcov, "LH:{}", lines_hit);
+            let _ = writeln!(lcov, "end_of_record");
+        }
+
+        lcov
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RAM with ops written from 0x200
+    fn ram_with(ops: &[u16]) -> Vec<u8> {
+        let mut ram = vec![0; 0x1000];
+        for (idx, op) in ops.iter().enumerate() {
+            ram[0x200 + idx * 2..0x202 + idx * 2].copy_from_slice(&op.to_be_bytes());
+        }
+        ram
+    }
+
+    #[test]
+    fn lcov_counts_lines_and_branches() {
+        // 3005 - skip, 6001, 1204 - never reached
+        let ram = ram_with(&[0x3005, 0x6001, 0x1204]);
+        let mut coverage = Coverage::new(ram.len());
+        coverage.record(0x200, 0x3005, 0x204);
+        coverage.record(0x200, 0x3005, 0x202);
+        coverage.record(0x202, 0x6001, 0x204);
+
+        let lcov = coverage.lcov("test", &ram, 0x200, 0x205, |addr| {
+            Some(("game.8o".to_string(), (addr - 0x200) as u32 / 2 + 1))
+        });
+        assert_eq!(
+            lcov,
+            "TN:test\n\
+             SF:game.8o\n\
+             BRDA:1,0,0,1\n\
+             BRDA:1,0,1,1\n\
+             BRF:2\n\
+             BRH:2\n\
+             DA:1,2\n\
+             DA:2,1\n\
+             DA:3,0\n\
+             LF:3\n\
+             LH:2\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn lcov_merges_lines_and_reports_unrun_skips() {
+        // 4000 - skip that never runs, then two instructions on one line
+        let ram = ram_with(&[0x4000, 0x6001, 0x6002]);
+        let mut coverage = Coverage::new(ram.len());
+        coverage.record(0x202, 0x6001, 0x204);
+        coverage.record(0x204, 0x6002, 0x206);
+        coverage.record(0x204, 0x6002, 0x206);
+
+        let lcov = coverage.lcov("test", &ram, 0x200, 0x205, |addr| match addr {
+            0x200 => Some(("b.8o".to_string(), 5)),
+            0x202 | 0x204 => Some(("a.8o".to_string(), 6)),
+            _ => None,
+        });
+        assert_eq!(
+            lcov,
+            "TN:test\n\
+             SF:a.8o\n\
+             BRF:0\n\
+             BRH:0\n\
+             DA:6,3\n\
+             LF:1\n\
+             LH:1\n\
+             end_of_record\n\
+             SF:b.8o\n\
+             BRDA:5,0,0,-\n\
+             BRDA:5,0,1,-\n\
+             BRF:2\n\
+             BRH:0\n\
+             DA:5,0\n\
+             LF:1\n\
+             LH:0\n\
+             end_of_record\n"
+        );
+    }
+
+    #[test]
+    fn lcov_leaves_out_unlocated_addresses() {
+        let ram = ram_with(&[0x6001, 0x6002]);
+        let mut coverage = Coverage::new(ram.len());
+        coverage.record(0x200, 0x6001, 0x202);
+
+        let lcov = coverage.lcov("test", &ram, 0x200, 0x203, |addr| {
+            (addr == 0x202).then(|| ("game.8o".to_string(), 2))
+        });
+        assert!(lcov.contains("DA:2,0\nLF:1\nLH:0\n"));
+        assert!(!lcov.contains("DA:1,"));
+    }
+}
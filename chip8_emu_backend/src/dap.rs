@@ -0,0 +1,668 @@
+use crate::Cpu;
+use crate::debugger::BreakReason;
+use crate::symbols::Symbols;
+use crate::tcp::Connection;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+
+// The CPU is reported to the editor as a single thread
+const THREAD_ID: u64 = 1;
+// variablesReference of the register scope
+const REGISTERS_REF: u64 = 1;
+// Most instructions a single step request runs before giving up
+const STEP_LIMIT: usize = 100_000;
+
+const REGISTER_NAMES: [&str; 5] = ["I", "PC", "SP", "DT", "ST"];
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (idx, b)| n | (*b as u32) << (16 - idx * 8));
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                text.push(BASE64[(n >> (18 - idx * 6)) as usize & 0x3F] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let value = BASE64.iter().position(|b| *b == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((n >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+// Decimal or 0x prefixed hex
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// Why execution stopped, as a stopped event reason
+fn stop_reason(reason: Option<BreakReason>) -> &'static str {
+    match reason {
+        Some(BreakReason::Breakpoint(_)) => "breakpoint",
+        Some(BreakReason::Watchpoint(_)) => "data breakpoint",
+        Some(BreakReason::StackOverflow(_) | BreakReason::StackUnderflow(_)) => "exception",
+        None => "pause",
+    }
+}
+
+// What the frontend should do after polling the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DapEvent {
+    // The editor attached, paused or stepped, stop running
+    Halt,
+    // The editor asked to continue
+    Resume,
+    // The editor disconnected
+    Detach,
+}
+
+// Debug Adapter Protocol server for editors, serving one client at a time.
+//
+// Editors connect over TCP (e.g. "debugServer" in a VS Code launch
+// configuration). Like GdbServer, poll it once per frame and call
+// report_stop when execution stops.
+//
+// launch arguments:
+//   program      ROM to load, replacing the running one
+//   symbols      symbol file, defaults to the ROM with a .sym extension
+//   sourceRoot   directory relative source paths are resolved against,
+//                defaults to the symbol file's directory
+//   stopOnEntry  stay paused once configured
+// attach takes sourceRoot and stopOnEntry and debugs the loaded ROM.
+pub struct DapServer {
+    conn: Connection,
+    // Sequence number of the next message we send
+    seq: u64,
+    source_root: Option<PathBuf>,
+    // Breakpoint addresses set by the editor, per source path
+    breakpoints: HashMap<String, Vec<u16>>,
+    stop_on_entry: bool,
+    // The editor expects a stopped event
+    running: bool,
+}
+
+impl DapServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            conn: Connection::bind(addr)?,
+            seq: 1,
+            source_root: None,
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            running: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.conn.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_connected()
+    }
+
+    // Accept an editor if none is attached and handle everything it sent
+    pub fn poll(&mut self, cpu: &mut Cpu) -> Option<DapEvent> {
+        let mut event = None;
+
+        if !self.conn.is_connected() {
+            if !self.conn.accept() {
+                return None;
+            }
+            self.breakpoints.clear();
+            self.running = false;
+        }
+
+        self.conn.receive();
+        while self.conn.is_connected()
+            && let Some(message) = self.next_message()
+        {
+            if message["type"] == "request"
+                && let Some(e) = self.handle(cpu, &message)
+            {
+                event = Some(e);
+            }
+        }
+
+        if !self.conn.is_connected() {
+            self.running = false;
+            event = Some(DapEvent::Detach);
+        }
+        // Remove the editor's breakpoints once it's gone
+        if event == Some(DapEvent::Detach) {
+            let debugger = cpu.debugger_mut();
+            for addr in self.breakpoints.drain().flat_map(|(_, addrs)| addrs) {
+                debugger.remove_breakpoint(addr);
+            }
+        }
+        event
+    }
+
+    // Send the stopped event the editor is waiting for after continuing.
+    // reason is None when stopped for anything but a break, e.g. pausing.
+    pub fn report_stop(&mut self, reason: Option<BreakReason>) {
+        if self.running {
+            self.running = false;
+            self.send_stopped(stop_reason(reason), reason);
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.conn.disconnect();
+        self.running = false;
+    }
+
+    // Split the next Content-Length framed message off the input
+    fn next_message(&mut self) -> Option<Value> {
+        let input = &mut self.conn.input;
+        loop {
+            let header_end = input.windows(4).position(|w| w == b"\r\n\r\n")?;
+            let header = String::from_utf8_lossy(&input[..header_end]);
+            let length = header.lines().find_map(|line| {
+                let (key, value) = line.split_once(':')?;
+                key.trim()
+                    .eq_ignore_ascii_case("Content-Length")
+                    .then(|| value.trim().parse::<usize>().ok())?
+            });
+
+            let body_start = header_end + 4;
+            let Some(length) = length else {
+                // Not a message we understand, drop the header
+                input.drain(..body_start);
+                continue;
+            };
+            if input.len() < body_start + length {
+                return None;
+            }
+            let message = serde_json::from_slice(&input[body_start..body_start + length]);
+            input.drain(..body_start + length);
+            if let Ok(message) = message {
+                return Some(message);
+            }
+        }
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let body = message.to_string();
+        let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        self.conn.send(framed.as_bytes());
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send_stopped(&mut self, reason: &str, hit: Option<BreakReason>) {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(hit) = hit {
+            body["description"] = json!(hit.to_string());
+        }
+        self.send_event("stopped", body);
+    }
+
+    // Handle one request and send its response, followed by any events
+    fn handle(&mut self, cpu: &mut Cpu, request: &Value) -> Option<DapEvent> {
+        let command = request["command"].as_str().unwrap_or("");
+        let args = &request["arguments"];
+        let mut event = None;
+        // Events to send once the response is out
+        let mut events = Vec::new();
+
+        let result: Result<Value, String> = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSetVariable": true,
+                "supportsSteppingGranularity": true,
+                "supportsReadMemoryRequest": true,
+                "supportsWriteMemoryRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" | "attach" => {
+                let loaded = if command == "launch" {
+                    self.launch(cpu, args)
+                } else {
+                    self.source_root = args["sourceRoot"].as_str().map(PathBuf::from);
+                    Ok(())
+                };
+                loaded.map(|_| {
+                    self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                    // Ready for breakpoints now that symbols are loaded
+                    events.push(("initialized", json!({})));
+                    event = Some(DapEvent::Halt);
+                    Value::Null
+                })
+            }
+            "configurationDone" => {
+                if self.stop_on_entry {
+                    event = Some(DapEvent::Halt);
+                    events.push(("stopped", json!({ "reason": "entry" })));
+                } else {
+                    self.running = true;
+                    event = Some(DapEvent::Resume);
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(cpu, args)),
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+            "stackTrace" => Ok(self.stack_trace(cpu)),
+            "scopes" => Ok(json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "variablesReference": REGISTERS_REF,
+                    "expensive": false,
+                }],
+            })),
+            "variables" => Ok(json!({ "variables": variables(cpu) })),
+            "setVariable" => set_variable(cpu, args),
+            "continue" => {
+                self.running = true;
+                event = Some(DapEvent::Resume);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                let instruction = args["granularity"] == "instruction";
+                let hit = self.step(cpu, command, instruction);
+                event = Some(DapEvent::Halt);
+                self.running = false;
+                events.push((
+                    "stopped",
+                    json!({ "reason": hit.map_or("step", |hit| stop_reason(Some(hit))) }),
+                ));
+                Ok(Value::Null)
+            }
+            "pause" => {
+                self.running = false;
+                event = Some(DapEvent::Halt);
+                events.push(("stopped", json!({ "reason": "pause" })));
+                Ok(Value::Null)
+            }
+            "readMemory" => read_memory(cpu, args),
+            "writeMemory" => write_memory(cpu, args),
+            "disconnect" | "terminate" => {
+                event = Some(DapEvent::Detach);
+                events.push(("terminated", json!({})));
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response);
+
+        for (name, mut body) in events {
+            if name == "stopped" {
+                body["threadId"] = json!(THREAD_ID);
+                body["allThreadsStopped"] = json!(true);
+            }
+            self.send_event(name, body);
+        }
+        if event == Some(DapEvent::Detach) {
+            self.disconnect();
+        }
+        event
+    }
+
+    // Load the ROM and symbols named in a launch request
+    fn launch(&mut self, cpu: &mut Cpu, args: &Value) -> Result<(), String> {
+        let program = Path::new(args["program"].as_str().ok_or("Missing program")?);
+        let rom = fs::read(program)
+            .map_err(|e| format!("Unable to read {}: {}", program.display(), e))?;
+        cpu.reset();
+        cpu.load(&rom)
+            .map_err(|e| format!("Unable to load ROM: {}", e))?;
+
+        let sym_path = match args["symbols"].as_str() {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(program.with_extension("sym")).filter(|path| path.exists()),
+        };
+        let symbols = match &sym_path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+                let symbols = Symbols::parse(&text)
+                    .map_err(|e| format!("Invalid symbols {}: {}", path.display(), e))?;
+                Some(symbols)
+            }
+            None => None,
+        };
+        cpu.debugger_mut().set_symbols(symbols);
+
+        self.source_root = match args["sourceRoot"].as_str() {
+            Some(root) => Some(PathBuf::from(root)),
+            None => sym_path.and_then(|path| path.parent().map(Path::to_path_buf)),
+        };
+        Ok(())
+    }
+
+    // Replace the breakpoints in one source file, mapping lines to
+    // addresses with the symbol file
+    fn set_breakpoints(&mut self, cpu: &mut Cpu, args: &Value) -> Value {
+        let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+        let debugger = cpu.debugger_mut();
+        for addr in self.breakpoints.remove(&path).unwrap_or_default() {
+            debugger.remove_breakpoint(addr);
+        }
+
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or(0);
+            let addr = debugger
+                .symbols()
+                .and_then(|symbols| symbols.addr_of_line(&path, line as u32));
+            match addr {
+                Some(addr) => {
+                    debugger.add_breakpoint(addr);
+                    addrs.push(addr);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": line,
+                        "instructionReference": format!("{:#05X}", addr),
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No instruction on this line",
+                })),
+            }
+        }
+        self.breakpoints.insert(path, addrs);
+
+        json!({ "breakpoints": breakpoints })
+    }
+
+    // The instruction at PC followed by the call site of each return address
+    fn stack_trace(&self, cpu: &Cpu) -> Value {
+        let mut addrs = vec![cpu.get_registers().pc];
+        addrs.extend(
+            cpu.get_call_stack()
+                .iter()
+                .rev()
+                .map(|ret| ret.wrapping_sub(2)),
+        );
+        let symbols = cpu.debugger().symbols();
+
+        let frames: Vec<Value> = addrs
+            .iter()
+            .enumerate()
+            .map(|(id, addr)| {
+                let name = symbols
+                    .and_then(|symbols| symbols.describe(*addr))
+                    .unwrap_or_else(|| format!("{:#05X}", addr));
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": format!("{:#05X}", addr),
+                });
+                if let Some((file, line)) = symbols.and_then(|symbols| symbols.source_line(*addr)) {
+                    frame["source"] = json!({
+                        "name": Path::new(file).file_name().map(|name| name.to_string_lossy()),
+                        "path": self.source_path(file),
+                    });
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+
+        json!({ "stackFrames": frames, "totalFrames": addrs.len() })
+    }
+
+    // Resolve a path from the symbol file against the source root
+    fn source_path(&self, file: &str) -> String {
+        match &self.source_root {
+            Some(root) if Path::new(file).is_relative() => root.join(file).display().to_string(),
+            _ => file.to_string(),
+        }
+    }
+
+    // Run until the step completes, returning the break that ended it early
+    fn step(&mut self, cpu: &mut Cpu, command: &str, instruction: bool) -> Option<BreakReason> {
+        let line_at = |cpu: &Cpu, addr: u16| {
+            cpu.debugger()
+                .symbols()
+                .and_then(|symbols| symbols.source_line(addr))
+                .cloned()
+        };
+        let start = cpu.get_registers();
+        let line = line_at(cpu, start.pc);
+
+        for _ in 0..STEP_LIMIT {
+            cpu.step();
+            if let Some(hit) = cpu.take_break() {
+                return Some(hit);
+            }
+            if let Some(error) = cpu.error() {
+                return Some(error.into());
+            }
+
+            let regs = cpu.get_registers();
+            let done = match command {
+                "stepOut" => regs.sp < start.sp,
+                // Step over calls
+                "next" if regs.sp > start.sp => false,
+                _ if instruction || line.is_none() => true,
+                // Stop on the next instruction with a different source line
+                _ => line_at(cpu, regs.pc).is_some_and(|next| Some(next) != line),
+            };
+            if done {
+                break;
+            }
+        }
+        None
+    }
+}
+
+fn variables(cpu: &Cpu) -> Vec<Value> {
+    let regs = cpu.get_registers();
+    let mut variables: Vec<Value> = regs
+        .v_reg
+        .iter()
+        .enumerate()
+        .map(|(idx, value)| {
+            json!({
+                "name": format!("V{:X}", idx),
+                "value": format!("{:#04X}", value),
+                "variablesReference": 0,
+            })
+        })
+        .collect();
+
+    let values = [
+        format!("{:#06X}", regs.i_reg),
+        format!("{:#06X}", regs.pc),
+        regs.sp.to_string(),
+        format!("{:#04X}", regs.delay_t),
+        format!("{:#04X}", regs.sound_t),
+    ];
+    for (name, value) in REGISTER_NAMES.iter().zip(values) {
+        let mut variable = json!({ "name": name, "value": value, "variablesReference": 0 });
+        // Let the editor open a memory view at I and PC
+        if *name == "I" || *name == "PC" {
+            variable["memoryReference"] = json!(value);
+        }
+        variables.push(variable);
+    }
+    variables
+}
+
+fn set_variable(cpu: &mut Cpu, args: &Value) -> Result<Value, String> {
+    let name = args["name"].as_str().unwrap_or("");
+    let text = args["value"].as_str().unwrap_or("");
+    let value = parse_number(text).ok_or_else(|| format!("Invalid value: {}", text))?;
+    let mut regs = cpu.get_registers();
+
+    let reg_idx = name
+        .strip_prefix('V')
+        .filter(|reg| reg.len() == 1)
+        .and_then(|reg| usize::from_str_radix(reg, 16).ok());
+    match (reg_idx, name) {
+        (Some(idx), _) => regs.v_reg[idx] = value as u8,
+        (None, "I") => regs.i_reg = value,
+        (None, "PC") => regs.pc = value as u16,
+        (None, "SP") => regs.sp = value as u16,
+        (None, "DT") => regs.delay_t = value as u8,
+        (None, "ST") => regs.sound_t = value as u8,
+        _ => return Err(format!("Unknown register: {}", name)),
+    }
+    cpu.set_registers(regs);
+
+    let variable = variables(cpu)
+        .into_iter()
+        .find(|variable| variable["name"] == name)
+        .unwrap_or_default();
+    Ok(json!({ "value": variable["value"] }))
+}
+
+// Start address of a memoryReference plus offset, which mustn't be negative
+fn memory_addr(args: &Value) -> Result<usize, String> {
+    let reference = args["memoryReference"].as_str().unwrap_or("");
+    let base = parse_number(reference).ok_or_else(|| format!("Invalid memory: {}", reference))?;
+    let addr = (base as i64).saturating_add(args["offset"].as_i64().unwrap_or(0));
+    usize::try_from(addr).map_err(|_| format!("Address out of range: {}", addr))
+}
+
+// Bytes past the end of memory are reported as unreadable
+fn read_memory(cpu: &Cpu, args: &Value) -> Result<Value, String> {
+    let addr = memory_addr(args)?;
+    let count = args["count"].as_u64().unwrap_or(0) as usize;
+    let ram = cpu.get_ram();
+    if addr >= ram.len() {
+        return Err(format!("Address out of range: {:#05X}", addr));
+    }
+
+    let end = addr.saturating_add(count).min(ram.len());
+    Ok(json!({
+        "address": format!("{:#05X}", addr),
+        "data": base64_encode(&ram[addr..end]),
+        "unreadableBytes": count - (end - addr),
+    }))
+}
+
+fn write_memory(cpu: &mut Cpu, args: &Value) -> Result<Value, String> {
+    let addr = memory_addr(args)?;
+    let data = args["data"]
+        .as_str()
+        .and_then(base64_decode)
+        .ok_or("Invalid data")?;
+    if addr.saturating_add(data.len()) > cpu.get_ram().len() {
+        return Err(format!("Address out of range: {:#05X}", addr));
+    }
+
+    for (offset, byte) in data.iter().enumerate() {
+        cpu.poke((addr + offset) as u16, *byte);
+    }
+    Ok(json!({ "bytesWritten": data.len() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioManager, Chip8Variant};
+
+    fn cpu_with_rom() -> Cpu {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        cpu.load(&[0x60, 0x05, 0x12, 0x02]).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn base64_round_trips() {
+        assert_eq!(base64_encode(b"CHIP-8"), "Q0hJUC04");
+        assert_eq!(base64_encode(&[0x60, 0x05]), "YAU=");
+        assert_eq!(base64_decode("YAU=").unwrap(), [0x60, 0x05]);
+        assert_eq!(base64_decode("*"), None);
+    }
+
+    #[test]
+    fn read_memory_reports_bytes_past_the_end_as_unreadable() {
+        let cpu = cpu_with_rom();
+        let read = read_memory(&cpu, &json!({ "memoryReference": "0x200", "count": 2 })).unwrap();
+        assert_eq!(read["address"], "0x200");
+        assert_eq!(read["data"], "YAU=");
+        assert_eq!(read["unreadableBytes"], 0);
+
+        let args = json!({ "memoryReference": "0xFFE", "offset": 1, "count": 4 });
+        let read = read_memory(&cpu, &args).unwrap();
+        assert_eq!(read["address"], "0xFFF");
+        assert_eq!(read["unreadableBytes"], 3);
+
+        // Counts that would overflow the end address
+        let args = json!({ "memoryReference": "0x200", "count": u64::MAX });
+        let read = read_memory(&cpu, &args).unwrap();
+        assert_eq!(read["unreadableBytes"], u64::MAX - 0xE00);
+    }
+
+    #[test]
+    fn read_memory_rejects_addresses_outside_memory() {
+        let cpu = cpu_with_rom();
+        for args in [
+            json!({ "memoryReference": "0x1000", "count": 1 }),
+            json!({ "memoryReference": "0x200", "offset": -0x201, "count": 1 }),
+            json!({ "memoryReference": "0", "offset": i64::MIN, "count": 1 }),
+            json!({ "memoryReference": "0xFFFFFFFF", "offset": i64::MAX, "count": 1 }),
+        ] {
+            assert!(read_memory(&cpu, &args).is_err(), "{}", args);
+        }
+    }
+
+    #[test]
+    fn write_memory_checks_range() {
+        let mut cpu = cpu_with_rom();
+        let args = json!({ "memoryReference": "0x300", "offset": 2, "data": "YAU=" });
+        assert_eq!(write_memory(&mut cpu, &args).unwrap()["bytesWritten"], 2);
+        assert_eq!(cpu.get_ram()[0x302..0x304], [0x60, 0x05]);
+
+        let past_end = json!({ "memoryReference": "0xFFF", "data": "YAU=" });
+        assert!(write_memory(&mut cpu, &past_end).is_err());
+        let negative = json!({ "memoryReference": "0", "offset": -1, "data": "YAU=" });
+        assert!(write_memory(&mut cpu, &negative).is_err());
+    }
+}
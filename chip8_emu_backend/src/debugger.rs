@@ -0,0 +1,255 @@
+use crate::error::CpuError;
+use crate::symbols::Symbols;
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
+// Number of memory accesses kept in the access log
+const ACCESS_LOG_SIZE: usize = 256;
+
+// Direction of a memory access
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+// Instruction (or fetch) that caused a memory access
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AccessSource {
+    // Opcode fetch
+    Fetch,
+    // DXYN sprite data
+    Draw,
+    // FX33 BCD store
+    Bcd,
+    // FX55 register store
+    Store,
+    // FX65 register load
+    Load,
+}
+
+// A single read or write of RAM
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    // Address of the instruction that made the access
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+    pub source: AccessSource,
+}
+
+impl fmt::Display for MemAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            AccessKind::Read => "read",
+            AccessKind::Write => "write",
+        };
+        write!(
+            f,
+            "{:04X}: {:?} {} [{:04X}] = {:02X}",
+            self.pc, self.source, kind, self.addr, self.value
+        )
+    }
+}
+
+// Which accesses trigger a watchpoint
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+// Watch an inclusive range of addresses
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    pub fn contains(&self, addr: u16) -> bool {
+        self.start <= addr && addr <= self.end
+    }
+
+    fn matches(&self, access: &MemAccess) -> bool {
+        let kind = matches!(
+            (self.kind, access.kind),
+            (WatchKind::ReadWrite, _)
+                | (WatchKind::Read, AccessKind::Read)
+                | (WatchKind::Write, AccessKind::Write)
+        );
+        kind && self.contains(access.addr)
+    }
+}
+
+// Why execution stopped
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BreakReason {
+    // PC reached a breakpoint
+    Breakpoint(u16),
+    // A watched address was accessed
+    Watchpoint(MemAccess),
+    // The instruction at this address would overflow the stack
+    StackOverflow(u16),
+    // The instruction at this address would underflow the stack
+    StackUnderflow(u16),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakReason::Breakpoint(addr) => write!(f, "breakpoint at {:04X}", addr),
+            BreakReason::Watchpoint(access) => write!(f, "watchpoint: {}", access),
+            BreakReason::StackOverflow(addr) => write!(f, "stack overflow at {:04X}", addr),
+            BreakReason::StackUnderflow(addr) => write!(f, "stack underflow at {:04X}", addr),
+        }
+    }
+}
+
+// The break a stack error would have been under StackPolicy::Break
+impl From<CpuError> for BreakReason {
+    fn from(error: CpuError) -> Self {
+        match error {
+            CpuError::StackOverflow { pc } => BreakReason::StackOverflow(pc),
+            CpuError::StackUnderflow { pc } => BreakReason::StackUnderflow(pc),
+        }
+    }
+}
+
+// Breakpoints, watchpoints and memory access tracing attached to the CPU
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    // Recent memory accesses, only recorded while logging is enabled
+    access_log: VecDeque<MemAccess>,
+    log_accesses: bool,
+    // Reason for the most recent unhandled break
+    hit: Option<BreakReason>,
+    // Labels for the loaded ROM
+    symbols: Option<Symbols>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn symbols(&self) -> Option<&Symbols> {
+        self.symbols.as_ref()
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    // Resolve a label (if symbols are loaded) or hex address
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        match &self.symbols {
+            Some(symbols) => symbols.resolve(text),
+            None => Symbols::new().resolve(text),
+        }
+    }
+
+    // Describe addr using the nearest label, falling back to hex
+    pub fn describe(&self, addr: u16) -> String {
+        match self.symbols.as_ref().and_then(|s| s.describe(addr)) {
+            Some(name) => format!("{:04X} <{}>", addr, name),
+            None => format!("{:04X}", addr),
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    // Add a breakpoint, or remove it if one is already set at addr
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // Remove every watchpoint overlapping the range start..=end
+    pub fn remove_watchpoints(&mut self, start: u16, end: u16) {
+        self.watchpoints.retain(|w| w.end < start || w.start > end);
+    }
+
+    pub fn access_log(&self) -> &VecDeque<MemAccess> {
+        &self.access_log
+    }
+
+    pub fn logging_accesses(&self) -> bool {
+        self.log_accesses
+    }
+
+    pub fn set_log_accesses(&mut self, enabled: bool) {
+        self.log_accesses = enabled;
+        if !enabled {
+            self.access_log.clear();
+        }
+    }
+
+    // Whether execution stopped and take_break hasn't been called since
+    pub fn has_break(&self) -> bool {
+        self.hit.is_some()
+    }
+
+    // Return and clear the reason execution stopped, if any
+    pub fn take_break(&mut self) -> Option<BreakReason> {
+        self.hit.take()
+    }
+
+    // Called by the CPU when it has to stop, e.g. on stack faults
+    pub(crate) fn on_break(&mut self, reason: BreakReason) {
+        if self.hit.is_none() {
+            self.hit = Some(reason);
+        }
+    }
+
+    // Called by the CPU whenever PC moves to a new instruction
+    pub(crate) fn on_pc(&mut self, pc: u16) {
+        if self.hit.is_none() && self.breakpoints.contains(&pc) {
+            self.hit = Some(BreakReason::Breakpoint(pc));
+        }
+    }
+
+    // Called by the CPU for every RAM read and write
+    pub(crate) fn on_access(&mut self, access: MemAccess) {
+        if self.log_accesses {
+            if self.access_log.len() == ACCESS_LOG_SIZE {
+                self.access_log.pop_front();
+            }
+            self.access_log.push_back(access);
+        }
+
+        if self.hit.is_none() && self.watchpoints.iter().any(|w| w.matches(&access)) {
+            self.hit = Some(BreakReason::Watchpoint(access));
+        }
+    }
+
+    // Forget per-run state, keeping breakpoints and watchpoints
+    pub(crate) fn reset(&mut self) {
+        self.access_log.clear();
+        self.hit = None;
+    }
+}
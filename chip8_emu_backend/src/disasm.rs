@@ -0,0 +1,180 @@
+use crate::config::Chip8Variant;
+use crate::symbols::Symbols;
+
+// Convert an opcode into a human readable mnemonic, decoding the opcodes
+// only the variant has
+pub fn disassemble(op: u16, variant: Chip8Variant) -> String {
+    disassemble_with_symbols(op, variant, None)
+}
+
+// Convert an opcode into a mnemonic, naming addresses that have labels
+pub fn disassemble_with_symbols(
+    op: u16,
+    variant: Chip8Variant,
+    symbols: Option<&Symbols>,
+) -> String {
+    let digit_1 = (op & 0xF000) >> 12;
+    let digit_2 = (op & 0x0F00) >> 8;
+    let digit_3 = (op & 0x00F0) >> 4;
+    let digit_4 = op & 0x000F;
+    let x = digit_2;
+    let y = digit_3;
+    let nn = op & 0xFF;
+    let nnn = op & 0x0FFF;
+    let target = match symbols.and_then(|symbols| symbols.label(nnn)) {
+        Some(label) => label.to_string(),
+        None => format!("{:#05X}", nnn),
+    };
+    let mega = variant == Chip8Variant::MegaChip;
+    let chip8x = variant == Chip8Variant::Chip8X;
+
+    match digit_1 {
+        0x0 => match (digit_2, digit_3, digit_4) {
+            (0x0, 0xB, n) if mega => format!("SCU {}", n),
+            (0x0, 0xC, n) => format!("SCD {}", n),
+            (0x0, 0xE, 0x0) => "CLS".to_string(),
+            (0x0, 0xE, 0xE) => "RET".to_string(),
+            (0x0, 0xF, 0xB) => "SCR".to_string(),
+            (0x0, 0xF, 0xC) => "SCL".to_string(),
+            (0x0, 0xF, 0xD) => "EXIT".to_string(),
+            (0x0, 0xF, 0xE) => "LOW".to_string(),
+            (0x0, 0xF, 0xF) => "HIGH".to_string(),
+            (0x0, 0x1, 0x0) if mega => "MEGAOFF".to_string(),
+            (0x0, 0x1, 0x1) if mega => "MEGAON".to_string(),
+            // The low 16 bits of I are the next word
+            (0x1, _, _) if mega => format!("LDHI I, {:#04X}", nn),
+            (0x2, _, _) if mega => format!("LDPAL {:#04X}", nn),
+            (0x3, _, _) if mega => format!("SPRW {:#04X}", nn),
+            (0x4, _, _) if mega => format!("SPRH {:#04X}", nn),
+            (0x5, _, _) if mega => format!("ALPHA {:#04X}", nn),
+            (0x6, 0x0, n) if mega => format!("DIGISND {}", n),
+            (0x7, 0x0, 0x0) if mega => "STOPSND".to_string(),
+            (0x8, 0x0, n) if mega => format!("BMODE {}", n),
+            (0x9, _, _) if mega => format!("CCOL {:#04X}", nn),
+            (0x2, 0xA, 0x0) if chip8x => "BGCOL".to_string(),
+            (0x2, 0x3, 0x0) if variant == Chip8Variant::HiresChip8 => "CLS".to_string(),
+            _ => format!("SYS {:#05X}", nnn),
+        },
+        0x1 => format!("JP {}", target),
+        0x2 => format!("CALL {}", target),
+        0x3 => format!("SE V{:X}, {:#04X}", x, nn),
+        0x4 => format!("SNE V{:X}, {:#04X}", x, nn),
+        0x5 if digit_4 == 0x0 => format!("SE V{:X}, V{:X}", x, y),
+        // Adds each nibble separately
+        0x5 if digit_4 == 0x1 && chip8x => format!("ADDN V{:X}, V{:X}", x, y),
+        0x6 => format!("LD V{:X}, {:#04X}", x, nn),
+        0x7 => format!("ADD V{:X}, {:#04X}", x, nn),
+        0x8 => match digit_4 {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => unknown(op),
+        },
+        0x9 if digit_4 == 0x0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA => format!("LD I, {}", target),
+        0xB if chip8x => format!("COL V{:X}, V{:X}, {}", x, y, digit_4),
+        0xB => format!("JP V0, {}", target),
+        0xC => format!("RND V{:X}, {:#04X}", x, nn),
+        0xD => format!("DRW V{:X}, V{:X}, {}", x, y, digit_4),
+        0xE => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            // Second keypad
+            0xF2 if chip8x => format!("SKP2 V{:X}", x),
+            0xF5 if chip8x => format!("SKNP2 V{:X}", x),
+            _ => unknown(op),
+        },
+        0xF => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            0xF8 if chip8x => format!("OUT V{:X}", x),
+            0xFB if chip8x => format!("IN V{:X}", x),
+            _ => unknown(op),
+        },
+        _ => unknown(op),
+    }
+}
+
+// Raw data that doesn't decode to an instruction
+fn unknown(op: u16) -> String {
+    format!("DW {:#06X}", op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_hires_chip8_clear_screen() {
+        assert_eq!(disassemble(0x0230, Chip8Variant::HiresChip8), "CLS");
+        assert_eq!(disassemble(0x0230, Chip8Variant::Chip8), "SYS 0x230");
+        // Jump HIRES ROMs start with, the interpreter starts them at 0x2C0
+        assert_eq!(disassemble(0x1260, Chip8Variant::HiresChip8), "JP 0x260");
+    }
+
+    #[test]
+    fn decodes_chip8x_opcodes() {
+        let x = Chip8Variant::Chip8X;
+        for (op, text) in [
+            (0x02A0, "BGCOL"),
+            (0x5121, "ADDN V1, V2"),
+            (0xB120, "COL V1, V2, 0"),
+            (0xB12F, "COL V1, V2, 15"),
+            (0xE3F2, "SKP2 V3"),
+            (0xE3F5, "SKNP2 V3"),
+            (0xF4F8, "OUT V4"),
+            (0xF4FB, "IN V4"),
+        ] {
+            assert_eq!(disassemble(op, x), text);
+        }
+        assert_eq!(disassemble(0x5121, Chip8Variant::Chip8), "DW 0x5121");
+        assert_eq!(disassemble(0xB120, Chip8Variant::Chip8), "JP V0, 0x120");
+        assert_eq!(disassemble(0xF4FB, Chip8Variant::Chip8), "DW 0xF4FB");
+    }
+
+    #[test]
+    fn decodes_megachip_opcodes() {
+        let mega = Chip8Variant::MegaChip;
+        for (op, text) in [
+            (0x0010, "MEGAOFF"),
+            (0x0011, "MEGAON"),
+            (0x0112, "LDHI I, 0x12"),
+            (0x0204, "LDPAL 0x04"),
+            (0x0310, "SPRW 0x10"),
+            (0x0408, "SPRH 0x08"),
+            (0x05FF, "ALPHA 0xFF"),
+            (0x0600, "DIGISND 0"),
+            (0x0601, "DIGISND 1"),
+            (0x0700, "STOPSND"),
+            (0x0802, "BMODE 2"),
+            (0x0901, "CCOL 0x01"),
+            (0x00B3, "SCU 3"),
+        ] {
+            assert_eq!(disassemble(op, mega), text);
+        }
+        assert_eq!(disassemble(0x0204, Chip8Variant::SuperChip11), "SYS 0x204");
+    }
+
+    #[test]
+    fn names_labels_with_symbols() {
+        let symbols = Symbols::parse("0x240 loop\n").unwrap();
+        let op = disassemble_with_symbols(0x1240, Chip8Variant::Chip8, Some(&symbols));
+        assert_eq!(op, "JP loop");
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+// Errors that can occur when loading a ROM into memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    // ROM contains no data
+    Empty,
+    // Load address lies outside of the variant's address space
+    InvalidAddress { addr: u16, ram_size: usize },
+    // ROM does not fit between the load address and the end of memory
+    TooLarge { size: usize, max_size: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::Empty => write!(f, "ROM file is empty"),
+            LoadError::InvalidAddress { addr, ram_size } => write!(
+                f,
+                "load address {:#05X} is outside of the {} byte address space",
+                addr, ram_size
+            ),
+            LoadError::TooLarge { size, max_size } => write!(
+                f,
+                "ROM is too large ({} bytes, maximum is {} bytes)",
+                size, max_size
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+// Error in a text file (trace, symbols or ROM database), with the 1-based line it occurred on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+// Errors that halt the CPU while running
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CpuError {
+    // 2NNN with every stack slot in use
+    StackOverflow { pc: u16 },
+    // 00EE with an empty stack
+    StackUnderflow { pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::StackOverflow { pc } => write!(f, "stack overflow at {:04X}", pc),
+            CpuError::StackUnderflow { pc } => write!(f, "stack underflow at {:04X}", pc),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
@@ -0,0 +1,243 @@
+// Screen contents in any of the variants' pixel formats, and their
+// conversion to RGBA for frontends to upload as a texture.
+
+// Black and white, for Mono screens
+pub const MONO_PALETTE: [u32; 2] = [0xFF000000, 0xFFFFFFFF];
+
+// Enumerable containing what the value of a pixel means
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    // 1 bit, on or off
+    Mono,
+    // 2 bits, one from each of two bit planes
+    TwoPlane,
+    // 8-bit palette index
+    Indexed8,
+    // 32-bit ARGB color
+    Argb,
+}
+
+impl PixelFormat {
+    // Largest pixel value of the format
+    pub fn max_value(&self) -> u32 {
+        match self {
+            PixelFormat::Mono => 0x1,
+            PixelFormat::TwoPlane => 0x3,
+            PixelFormat::Indexed8 => 0xFF,
+            PixelFormat::Argb => 0xFFFFFFFF,
+        }
+    }
+}
+
+// Grid of pixels, a u32 each whatever the format
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+impl Framebuffer {
+    // Blank framebuffer, all pixels 0
+    pub fn new(format: PixelFormat, width: usize, height: usize) -> Self {
+        Self {
+            format,
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn get_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn get_width(&self) -> usize {
+        self.width
+    }
+
+    pub fn get_height(&self) -> usize {
+        self.height
+    }
+
+    // Return the pixels, row by row
+    pub fn get_pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    // Return the pixel at (x, y)
+    pub fn get(&self, x: usize, y: usize) -> u32 {
+        self.pixels[x + self.width * y]
+    }
+
+    // Set the pixel at (x, y), masked to the format
+    pub fn set(&mut self, x: usize, y: usize, value: u32) {
+        self.pixels[x + self.width * y] = value & self.format.max_value();
+    }
+
+    // Set every pixel to 0
+    pub fn clear(&mut self) {
+        self.pixels.fill(0);
+    }
+
+    // Scroll by (dx, dy) pixels, blanking what scrolls in
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let old = self.pixels.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let from_x = x as isize - dx;
+                let from_y = y as isize - dy;
+                let inside = (0..self.width as isize).contains(&from_x)
+                    && (0..self.height as isize).contains(&from_y);
+                self.pixels[x + self.width * y] = if inside {
+                    old[from_x as usize + self.width * from_y as usize]
+                } else {
+                    0
+                };
+            }
+        }
+    }
+
+    // Copy of the picture in another size and format, each pixel taken from
+    // the one it covers in this framebuffer
+    pub fn resized(&self, format: PixelFormat, width: usize, height: usize) -> Self {
+        let mut resized = Self::new(format, width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = self.get(x * self.width / width, y * self.height / height);
+                resized.set(x, y, value);
+            }
+        }
+        resized
+    }
+
+    // Convert to RGBA, 4 bytes per pixel row by row, ready to upload as a
+    // texture. Pixel values index the palette of ARGB colors, values past its
+    // end are black. Argb pixels are converted as they are
+    pub fn to_rgba(&self, palette: &[u32]) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&value| {
+                let argb = match self.format {
+                    PixelFormat::Argb => value,
+                    _ => palette.get(value as usize).copied().unwrap_or(0xFF000000),
+                };
+                let [a, r, g, b] = argb.to_be_bytes();
+                [r, g, b, a]
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x3 framebuffer with pixels numbered 1 - 9 row by row
+    fn numbered() -> Framebuffer {
+        let mut fb = Framebuffer::new(PixelFormat::Indexed8, 3, 3);
+        for y in 0..3 {
+            for x in 0..3 {
+                fb.set(x, y, (1 + x + 3 * y) as u32);
+            }
+        }
+        fb
+    }
+
+    fn rows(fb: &Framebuffer) -> Vec<&[u32]> {
+        fb.get_pixels().chunks(fb.get_width()).collect()
+    }
+
+    #[test]
+    fn mono_uses_the_palette() {
+        let mut fb = Framebuffer::new(PixelFormat::Mono, 2, 1);
+        fb.set(0, 0, 1);
+        assert_eq!(
+            fb.to_rgba(&MONO_PALETTE),
+            [0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF]
+        );
+        // Only the low bit is kept
+        fb.set(1, 0, 2);
+        assert_eq!(fb.get(1, 0), 0);
+    }
+
+    #[test]
+    fn two_plane_indexes_four_colors() {
+        let palette = [0xFF000000, 0xFFFF0000, 0xFF00FF00, 0xFF0000FF];
+        let mut fb = Framebuffer::new(PixelFormat::TwoPlane, 4, 1);
+        for x in 0..4 {
+            fb.set(x, 0, x as u32);
+        }
+        assert_eq!(
+            fb.to_rgba(&palette),
+            [
+                0, 0, 0, 0xFF, 0xFF, 0, 0, 0xFF, 0, 0xFF, 0, 0xFF, 0, 0, 0xFF, 0xFF
+            ]
+        );
+        // Values past the end of the palette are black
+        assert_eq!(
+            fb.to_rgba(&MONO_PALETTE)[8..],
+            [0, 0, 0, 0xFF, 0, 0, 0, 0xFF]
+        );
+    }
+
+    #[test]
+    fn indexed8_masks_to_a_byte() {
+        let mut palette = [0xFF000000; 256];
+        palette[0xFF] = 0xFF123456;
+        let mut fb = Framebuffer::new(PixelFormat::Indexed8, 1, 1);
+        fb.set(0, 0, 0x1FF);
+        assert_eq!(fb.get(0, 0), 0xFF);
+        assert_eq!(fb.to_rgba(&palette), [0x12, 0x34, 0x56, 0xFF]);
+    }
+
+    #[test]
+    fn argb_converts_without_a_palette() {
+        let mut fb = Framebuffer::new(PixelFormat::Argb, 1, 1);
+        fb.set(0, 0, 0x80112233);
+        assert_eq!(fb.to_rgba(&[]), [0x11, 0x22, 0x33, 0x80]);
+    }
+
+    #[test]
+    fn scroll_blanks_what_scrolls_in() {
+        let mut fb = numbered();
+        fb.scroll(1, 0);
+        assert_eq!(rows(&fb), [[0, 1, 2], [0, 4, 5], [0, 7, 8]]);
+
+        let mut fb = numbered();
+        fb.scroll(-1, -1);
+        assert_eq!(rows(&fb), [[5, 6, 0], [8, 9, 0], [0, 0, 0]]);
+
+        let mut fb = numbered();
+        fb.scroll(0, 2);
+        assert_eq!(rows(&fb), [[0, 0, 0], [0, 0, 0], [1, 2, 3]]);
+    }
+
+    #[test]
+    fn scroll_past_the_size_blanks_everything() {
+        let mut fb = numbered();
+        fb.scroll(3, 0);
+        assert!(fb.get_pixels().iter().all(|pixel| *pixel == 0));
+
+        let mut fb = numbered();
+        fb.scroll(0, -5);
+        assert!(fb.get_pixels().iter().all(|pixel| *pixel == 0));
+    }
+
+    #[test]
+    fn resized_scales_and_converts() {
+        let mut fb = Framebuffer::new(PixelFormat::Mono, 2, 1);
+        fb.set(1, 0, 1);
+        let doubled = fb.resized(PixelFormat::Mono, 4, 2);
+        assert_eq!(rows(&doubled), [[0, 0, 1, 1], [0, 0, 1, 1]]);
+
+        let halved = numbered().resized(PixelFormat::Indexed8, 1, 1);
+        assert_eq!(halved.get_pixels(), [1]);
+
+        // Values are masked to the new format
+        let mono = numbered().resized(PixelFormat::Mono, 3, 1);
+        assert_eq!(mono.get_format(), PixelFormat::Mono);
+        assert_eq!(mono.get_pixels(), [1, 0, 1]);
+    }
+}
@@ -0,0 +1,697 @@
+use crate::debugger::{AccessKind, BreakReason, WatchKind, Watchpoint};
+use crate::tcp::Connection;
+use crate::{Cpu, Registers};
+use std::collections::BTreeSet;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+// Largest packet we accept, advertised in qSupported
+const PACKET_SIZE: usize = 4096;
+
+// Register block for 'g' and 'G', in order (16-bit registers are big endian):
+//   0-15  V0-VF  1 byte each
+//   16    I      2 bytes, the low 16 bits of MegaChip's 24-bit I (writes
+//                keep the high 8)
+//   17    PC     2 bytes
+//   18    SP     1 byte
+//   19    DT     1 byte
+//   20    ST     1 byte
+const REGISTER_BYTES: usize = 23;
+
+// Byte offset and size of a register in the register block
+fn register_span(reg: usize) -> Option<(usize, usize)> {
+    match reg {
+        0..=15 => Some((reg, 1)),
+        16 => Some((16, 2)),
+        17 => Some((18, 2)),
+        18..=20 => Some((reg + 2, 1)),
+        _ => None,
+    }
+}
+
+fn encode_registers(regs: &Registers) -> Vec<u8> {
+    let mut bytes = regs.v_reg.to_vec();
+    bytes.extend((regs.i_reg as u16).to_be_bytes());
+    bytes.extend(regs.pc.to_be_bytes());
+    bytes.push(regs.sp as u8);
+    bytes.push(regs.delay_t);
+    bytes.push(regs.sound_t);
+    bytes
+}
+
+// Registers written by the client, over the current ones
+fn decode_registers(bytes: &[u8], current: &Registers) -> Option<Registers> {
+    if bytes.len() != REGISTER_BYTES {
+        return None;
+    }
+    let mut v_reg = [0; 16];
+    v_reg.copy_from_slice(&bytes[..16]);
+    Some(Registers {
+        v_reg,
+        i_reg: current.i_reg & !0xFFFF | u16::from_be_bytes([bytes[16], bytes[17]]) as u32,
+        pc: u16::from_be_bytes([bytes[18], bytes[19]]),
+        sp: bytes[20] as u16,
+        delay_t: bytes[21],
+        sound_t: bytes[22],
+    })
+}
+
+// Packet as sent over the wire, "$data#checksum"
+fn frame(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, checksum)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|idx| u8::from_str_radix(&text[idx..idx + 2], 16).ok())
+        .collect()
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+// Stop reply describing why execution stopped
+fn stop_reply(reason: Option<BreakReason>) -> String {
+    match reason {
+        Some(BreakReason::Watchpoint(access)) => {
+            let kind = match access.kind {
+                AccessKind::Read => "rwatch",
+                AccessKind::Write => "watch",
+            };
+            format!("T05{}:{:x};", kind, access.addr)
+        }
+        // SIGSEGV
+        Some(BreakReason::StackOverflow(_) | BreakReason::StackUnderflow(_)) => "S0b".to_string(),
+        // SIGTRAP
+        _ => "S05".to_string(),
+    }
+}
+
+// What the frontend should do after polling the server
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GdbEvent {
+    // A client attached or interrupted, stop running
+    Halt,
+    // The client asked to continue
+    Resume,
+    // The client detached or disconnected
+    Detach,
+}
+
+// Something read from the client
+enum Input {
+    Packet(String),
+    // Ctrl-C
+    Interrupt,
+    BadChecksum,
+}
+
+// GDB remote serial protocol stub, serving one client at a time.
+//
+// The socket is non-blocking: call poll once per frame to accept clients and
+// handle their requests. Continuing is left to the caller (see GdbEvent), which
+// must call report_stop when execution stops so the client gets its reply.
+pub struct GdbServer {
+    conn: Connection,
+    // Client sent QStartNoAckMode
+    no_ack: bool,
+    // Client is waiting for a stop reply to 'c'
+    running: bool,
+    // Breakpoints and watchpoint ranges set by the client, removed when it
+    // detaches
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<(u16, u16)>,
+}
+
+impl GdbServer {
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            conn: Connection::bind(addr)?,
+            no_ack: false,
+            running: false,
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.conn.local_addr()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.conn.is_connected()
+    }
+
+    // Accept a client if none is attached and handle everything it sent
+    pub fn poll(&mut self, cpu: &mut Cpu) -> Option<GdbEvent> {
+        let mut event = None;
+
+        if !self.conn.is_connected() {
+            if !self.conn.accept() {
+                return None;
+            }
+            self.no_ack = false;
+            self.running = false;
+            event = Some(GdbEvent::Halt);
+        }
+
+        self.conn.receive();
+        while self.conn.is_connected()
+            && let Some(input) = self.next_input()
+        {
+            match input {
+                Input::Packet(packet) => {
+                    if !self.no_ack {
+                        self.send_raw(b"+");
+                    }
+                    if let Some(e) = self.handle(cpu, &packet) {
+                        event = Some(e);
+                    }
+                }
+                Input::Interrupt => {
+                    if self.running {
+                        self.running = false;
+                        // SIGINT
+                        self.send("S02");
+                    }
+                    event = Some(GdbEvent::Halt);
+                }
+                Input::BadChecksum => {
+                    if !self.no_ack {
+                        self.send_raw(b"-");
+                    }
+                }
+            }
+        }
+
+        if !self.conn.is_connected() {
+            self.running = false;
+            event = Some(GdbEvent::Detach);
+        }
+        if event == Some(GdbEvent::Detach) {
+            self.remove_breakpoints(cpu);
+        }
+        event
+    }
+
+    // Tell a client waiting on 'c' why execution stopped.
+    // reason is None when stopped for anything but a break, e.g. pausing.
+    pub fn report_stop(&mut self, reason: Option<BreakReason>) {
+        if self.running {
+            self.running = false;
+            self.send(&stop_reply(reason));
+        }
+    }
+
+    // Z/z type,addr,kind - 0 and 1 are breakpoints, 2-4 are write, read and
+    // access watchpoints covering kind bytes
+    fn set_breakpoint(&mut self, cpu: &mut Cpu, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(addr), Some(len)) = (parts.next(), parts.next(), parts.next()) else {
+            return "E01".to_string();
+        };
+        let (Some(addr), Ok(len)) = (parse_addr(addr), u16::from_str_radix(len, 16)) else {
+            return "E01".to_string();
+        };
+        let end = addr.saturating_add(len.max(1) - 1);
+        let debugger = cpu.debugger_mut();
+
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    debugger.add_breakpoint(addr);
+                    self.breakpoints.insert(addr);
+                } else {
+                    debugger.remove_breakpoint(addr);
+                    self.breakpoints.remove(&addr);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return String::new(),
+        };
+        if insert {
+            debugger.add_watchpoint(Watchpoint {
+                start: addr,
+                end,
+                kind: watch_kind,
+            });
+            self.watchpoints.push((addr, end));
+        } else {
+            debugger.remove_watchpoints(addr, end);
+            self.watchpoints.retain(|range| *range != (addr, end));
+        }
+        "OK".to_string()
+    }
+
+    // Remove the breakpoints and watchpoints the client set, once it's gone
+    fn remove_breakpoints(&mut self, cpu: &mut Cpu) {
+        let debugger = cpu.debugger_mut();
+        for addr in std::mem::take(&mut self.breakpoints) {
+            debugger.remove_breakpoint(addr);
+        }
+        for (start, end) in std::mem::take(&mut self.watchpoints) {
+            debugger.remove_watchpoints(start, end);
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.conn.disconnect();
+        self.running = false;
+    }
+
+    // Split the next packet or interrupt off the input, skipping acks
+    fn next_input(&mut self) -> Option<Input> {
+        let input = &mut self.conn.input;
+        loop {
+            match *input.first()? {
+                0x03 => {
+                    input.remove(0);
+                    return Some(Input::Interrupt);
+                }
+                b'$' => {
+                    // Wait for the rest of the packet and its two checksum digits
+                    let end = input.iter().position(|b| *b == b'#')?;
+                    if input.len() < end + 3 {
+                        return None;
+                    }
+                    let data = &input[1..end];
+                    let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                    let checksum = std::str::from_utf8(&input[end + 1..end + 3])
+                        .ok()
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    let packet = String::from_utf8_lossy(data).into_owned();
+                    input.drain(..end + 3);
+
+                    return Some(if checksum == Some(sum) {
+                        Input::Packet(packet)
+                    } else {
+                        Input::BadChecksum
+                    });
+                }
+                // Acks and noise between packets
+                _ => {
+                    input.remove(0);
+                }
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) {
+        self.send_raw(frame(data).as_bytes());
+    }
+
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.conn.send(bytes);
+    }
+
+    // Handle one packet, replying to it unless it resumes execution
+    fn handle(&mut self, cpu: &mut Cpu, packet: &str) -> Option<GdbEvent> {
+        let Some(cmd) = packet.chars().next() else {
+            self.send("");
+            return None;
+        };
+        let args = &packet[cmd.len_utf8()..];
+
+        let reply = match cmd {
+            '?' => stop_reply(None),
+            'g' => to_hex(&encode_registers(&cpu.get_registers())),
+            'G' => match from_hex(args)
+                .and_then(|bytes| decode_registers(&bytes, &cpu.get_registers()))
+            {
+                Some(regs) => {
+                    cpu.set_registers(regs);
+                    "OK".to_string()
+                }
+                None => "E01".to_string(),
+            },
+            'p' => {
+                let regs = encode_registers(&cpu.get_registers());
+                match usize::from_str_radix(args, 16).ok().and_then(register_span) {
+                    Some((offset, size)) => to_hex(&regs[offset..offset + size]),
+                    None => "E01".to_string(),
+                }
+            }
+            'P' => {
+                let current = cpu.get_registers();
+                let mut regs = encode_registers(&current);
+                let span = args.split_once('=').and_then(|(reg, value)| {
+                    let (offset, size) = register_span(usize::from_str_radix(reg, 16).ok()?)?;
+                    let value = from_hex(value).filter(|value| value.len() == size)?;
+                    Some((offset, value))
+                });
+                match span {
+                    Some((offset, value)) => {
+                        regs[offset..offset + value.len()].copy_from_slice(&value);
+                        if let Some(regs) = decode_registers(&regs, &current) {
+                            cpu.set_registers(regs);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            'm' => {
+                let ram = cpu.get_ram();
+                let range = args.split_once(',').and_then(|(addr, len)| {
+                    let start = parse_addr(addr)? as usize;
+                    let len = usize::from_str_radix(len, 16).ok()?.min(PACKET_SIZE / 2);
+                    (start < ram.len()).then(|| start..(start + len).min(ram.len()))
+                });
+                match range {
+                    Some(range) => to_hex(&ram[range]),
+                    None => "E01".to_string(),
+                }
+            }
+            'M' => {
+                let ram_len = cpu.get_ram().len();
+                let write = args.split_once(':').and_then(|(location, data)| {
+                    let (addr, len) = location.split_once(',')?;
+                    let addr = parse_addr(addr)?;
+                    let data = from_hex(data)?;
+                    let fits = usize::from_str_radix(len, 16).ok()? == data.len()
+                        && addr as usize + data.len() <= ram_len;
+                    fits.then_some((addr, data))
+                });
+                match write {
+                    Some((addr, data)) => {
+                        for (offset, byte) in data.into_iter().enumerate() {
+                            cpu.poke(addr + offset as u16, byte);
+                        }
+                        "OK".to_string()
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            'c' | 's' => {
+                // Optional address to resume from
+                if let Some(pc) = parse_addr(args) {
+                    let mut regs = cpu.get_registers();
+                    regs.pc = pc;
+                    cpu.set_registers(regs);
+                }
+                if cmd == 'c' {
+                    self.running = true;
+                    return Some(GdbEvent::Resume);
+                }
+                cpu.step();
+                match cpu.take_break() {
+                    Some(reason) => stop_reply(Some(reason)),
+                    None if cpu.error().is_some() => "S0b".to_string(),
+                    None => stop_reply(None),
+                }
+            }
+            'Z' | 'z' => self.set_breakpoint(cpu, cmd == 'Z', args),
+            'q' => match args.split(':').next().unwrap_or("") {
+                "Supported" => format!("PacketSize={:x};QStartNoAckMode+", PACKET_SIZE),
+                "Attached" => "1".to_string(),
+                "fThreadInfo" => "m1".to_string(),
+                "sThreadInfo" => "l".to_string(),
+                "C" => "QC1".to_string(),
+                _ => String::new(),
+            },
+            'Q' if args == "StartNoAckMode" => {
+                self.send("OK");
+                self.no_ack = true;
+                return None;
+            }
+            // Only one thread
+            'H' => "OK".to_string(),
+            'D' => {
+                self.send("OK");
+                self.disconnect();
+                return Some(GdbEvent::Detach);
+            }
+            'k' => {
+                self.disconnect();
+                return Some(GdbEvent::Detach);
+            }
+            // Anything else is unsupported, which is an empty reply
+            _ => String::new(),
+        };
+
+        self.send(&reply);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioManager, Chip8Variant};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    // 6005 6106 1204: V0 = 5, V1 = 6, then jump to itself forever
+    const ROM: [u8; 6] = [0x60, 0x05, 0x61, 0x06, 0x12, 0x04];
+    // Polls before giving up on a reply
+    const MAX_POLLS: usize = 500;
+
+    // A server on a free localhost port with a client attached
+    struct Session {
+        server: GdbServer,
+        cpu: Cpu,
+        client: TcpStream,
+    }
+
+    impl Session {
+        fn new(variant: Chip8Variant) -> Self {
+            let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+            let client = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+            client
+                .set_read_timeout(Some(Duration::from_millis(10)))
+                .unwrap();
+            let mut cpu = Cpu::new(AudioManager::silent(), variant);
+            cpu.load(&ROM).unwrap();
+
+            let mut event = None;
+            for _ in 0..MAX_POLLS {
+                event = server.poll(&mut cpu);
+                if server.is_connected() {
+                    break;
+                }
+                sleep(Duration::from_millis(1));
+            }
+            assert_eq!(event, Some(GdbEvent::Halt));
+            Self {
+                server,
+                cpu,
+                client,
+            }
+        }
+
+        // Send a packet without waiting for anything
+        fn send(&mut self, packet: &str) {
+            self.client.write_all(frame(packet).as_bytes()).unwrap();
+        }
+
+        // Send a packet and return the reply
+        fn request(&mut self, packet: &str) -> String {
+            self.send(packet);
+            self.reply()
+        }
+
+        // Poll the server until a whole packet arrives, checking its
+        // checksum and skipping acks
+        fn reply(&mut self) -> String {
+            let mut received = Vec::new();
+            let mut buffer = [0; 1024];
+            for _ in 0..MAX_POLLS {
+                self.server.poll(&mut self.cpu);
+                if let Ok(len) = self.client.read(&mut buffer) {
+                    received.extend_from_slice(&buffer[..len]);
+                }
+
+                let Some(start) = received.iter().position(|b| *b == b'$') else {
+                    continue;
+                };
+                if let Some(end) = received.iter().position(|b| *b == b'#')
+                    && received.len() >= end + 3
+                {
+                    let data = String::from_utf8(received[start + 1..end].to_vec()).unwrap();
+                    assert_eq!(frame(&data).as_bytes(), &received[start..end + 3]);
+                    return data;
+                }
+            }
+            panic!("no reply from the server");
+        }
+    }
+
+    #[test]
+    fn frame_appends_checksum() {
+        assert_eq!(frame("OK"), "$OK#9a");
+        assert_eq!(frame(""), "$#00");
+        // The checksum wraps around
+        assert_eq!(frame("ffffff"), "$ffffff#64");
+    }
+
+    #[test]
+    fn next_input_splits_packets_interrupts_and_bad_checksums() {
+        let mut server = GdbServer::bind("127.0.0.1:0").unwrap();
+        server
+            .conn
+            .input
+            .extend_from_slice(b"+$g#67\x03-$m0,2#00$s");
+
+        assert!(matches!(server.next_input(), Some(Input::Packet(p)) if p == "g"));
+        assert!(matches!(server.next_input(), Some(Input::Interrupt)));
+        assert!(matches!(server.next_input(), Some(Input::BadChecksum)));
+        // The rest of the packet hasn't arrived yet
+        assert!(server.next_input().is_none());
+        server.conn.input.extend_from_slice(b"#73");
+        assert!(matches!(server.next_input(), Some(Input::Packet(p)) if p == "s"));
+        assert!(server.conn.input.is_empty());
+    }
+
+    #[test]
+    fn reads_and_writes_registers() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        let block = "00".repeat(16) + "0000" + "0200" + "000000";
+        assert_eq!(session.request("g"), block);
+
+        let block = concat!("0102030405060708090a0b0c0d0e0f10", "0345", "0208", "011213");
+        assert_eq!(session.request(&format!("G{}", block)), "OK");
+        let regs = session.cpu.get_registers();
+        assert_eq!(regs.v_reg[0xF], 0x10);
+        assert_eq!(regs.i_reg, 0x345);
+        assert_eq!(regs.pc, 0x208);
+        assert_eq!((regs.sp, regs.delay_t, regs.sound_t), (1, 0x12, 0x13));
+        assert_eq!(session.request("g"), block);
+
+        assert_eq!(session.request("p10"), "0345");
+        assert_eq!(session.request("P10=0abc"), "OK");
+        assert_eq!(session.cpu.get_registers().i_reg, 0xABC);
+        assert_eq!(session.request("P3=ff"), "OK");
+        assert_eq!(session.request("p3"), "ff");
+        assert_eq!(session.request("p11"), "0208");
+
+        assert_eq!(session.request("p15"), "E01");
+        assert_eq!(session.request("P11=02"), "E01");
+        assert_eq!(session.request("G0102"), "E01");
+    }
+
+    #[test]
+    fn register_writes_keep_high_byte_of_megachip_i() {
+        let mut session = Session::new(Chip8Variant::MegaChip);
+        let mut regs = session.cpu.get_registers();
+        regs.i_reg = 0xAB0000;
+        session.cpu.set_registers(regs);
+        assert_eq!(session.request("p10"), "0000");
+
+        assert_eq!(session.request("P10=1234"), "OK");
+        assert_eq!(session.cpu.get_registers().i_reg, 0xAB1234);
+
+        let block = "00".repeat(16) + "5678" + "0200" + "000000";
+        assert_eq!(session.request(&format!("G{}", block)), "OK");
+        assert_eq!(session.cpu.get_registers().i_reg, 0xAB5678);
+    }
+
+    #[test]
+    fn reads_and_writes_memory() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        assert_eq!(session.request("m200,6"), "600561061204");
+
+        assert_eq!(session.request("M300,2:abcd"), "OK");
+        assert_eq!(session.request("m300,2"), "abcd");
+
+        // Reads stop at the end of memory, writes must fit
+        assert_eq!(session.request("mffe,4"), "0000");
+        assert_eq!(session.request("m1000,1"), "E01");
+        assert_eq!(session.request("Mfff,2:0102"), "E01");
+        assert_eq!(session.request("M300,3:0102"), "E01");
+    }
+
+    #[test]
+    fn steps_one_instruction() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        assert_eq!(session.request("s"), "S05");
+        let regs = session.cpu.get_registers();
+        assert_eq!((regs.pc, regs.v_reg[0]), (0x202, 5));
+
+        // From an address
+        assert_eq!(session.request("s200"), "S05");
+        assert_eq!(session.cpu.get_registers().pc, 0x202);
+    }
+
+    #[test]
+    fn steps_over_a_draw_waiting_for_vblank() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        // D001 - draw with the display_wait quirk, which waits for vblank
+        assert_eq!(session.request("M300,2:d001"), "OK");
+        assert_eq!(session.request("s300"), "S05");
+        assert_eq!(session.cpu.get_registers().pc, 0x302);
+        assert!(!session.cpu.is_waiting_for_vblank());
+    }
+
+    #[test]
+    fn continues_to_a_breakpoint() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        assert_eq!(session.request("Z0,204,2"), "OK");
+
+        session.send("c");
+        let mut event = None;
+        for _ in 0..MAX_POLLS {
+            event = session.server.poll(&mut session.cpu);
+            if event.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        assert_eq!(event, Some(GdbEvent::Resume));
+
+        // Run like the frontend does until the breakpoint stops it
+        let mut reason = None;
+        for _ in 0..10 {
+            session.cpu.tick();
+            reason = session.cpu.take_break();
+            if reason.is_some() {
+                break;
+            }
+        }
+        assert_eq!(reason, Some(BreakReason::Breakpoint(0x204)));
+        session.server.report_stop(reason);
+        assert_eq!(session.reply(), "S05");
+        assert_eq!(session.request("p11"), "0204");
+
+        // Removed breakpoints don't stop execution
+        assert_eq!(session.request("z0,204,2"), "OK");
+        assert!(session.cpu.debugger().breakpoints().is_empty());
+    }
+
+    #[test]
+    fn detach_removes_the_clients_breakpoints() {
+        let mut session = Session::new(Chip8Variant::Chip8);
+        // Set from the debug panel, which outlives the client
+        session.cpu.debugger_mut().add_breakpoint(0x202);
+        assert_eq!(session.request("Z0,204,2"), "OK");
+        assert_eq!(session.request("Z2,300,2"), "OK");
+        assert_eq!(session.cpu.debugger().watchpoints().len(), 1);
+
+        assert_eq!(session.request("D"), "OK");
+        assert!(!session.server.is_connected());
+        assert_eq!(
+            session
+                .cpu
+                .debugger()
+                .breakpoints()
+                .iter()
+                .collect::<Vec<_>>(),
+            [&0x202]
+        );
+        assert!(session.cpu.debugger().watchpoints().is_empty());
+    }
+}
@@ -1,9 +1,39 @@
 pub mod audio;
+pub mod chip8x;
+pub mod compare;
 pub mod config;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
+pub mod disasm;
+pub mod error;
+pub mod framebuffer;
+pub mod gdb;
+pub mod megachip;
+pub mod profiler;
+pub mod romdb;
+pub mod symbols;
+mod tcp;
+pub mod timing;
+pub mod trace;
 
 pub use audio::AudioManager;
-pub use config::{Chip8Variant, DisplayMode, Quirks};
+pub use chip8x::{ColorBoard, IoPort};
+pub use config::{Chip8Variant, DisplayMode, Ips, LoResDxy0, MemoryIncrement, Quirks, StackPolicy};
+pub use coverage::Coverage;
+pub use dap::{DapEvent, DapServer};
+pub use debugger::{
+    AccessKind, AccessSource, BreakReason, Debugger, MemAccess, WatchKind, Watchpoint,
+};
+pub use error::{CpuError, LoadError, ParseError};
+pub use framebuffer::{Framebuffer, MONO_PALETTE, PixelFormat};
+pub use gdb::{GdbEvent, GdbServer};
+pub use megachip::{BlendMode, MEGA_HEIGHT, MEGA_WIDTH, MegaChip};
+pub use profiler::Profiler;
 use rand::random;
+pub use romdb::{RomDatabase, RomSettings};
+pub use symbols::Symbols;
+pub use trace::{TraceEntry, Tracer};
 
 // 16 sprites for each hexadecimal digit of size 5 bytes each
 const FONTSET_SIZE: usize = 80;
@@ -46,30 +76,29 @@ const HIRES_FONTSET: [u8; HIRES_FONTSET_SIZE] = [
 // 64x32 display
 pub const SCREEN_WIDTH: usize = 64;
 pub const SCREEN_HEIGHT: usize = 32;
+// 128x64 display in HiRes Mode
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
 
-// 4 KB of RAM
-const RAM_SIZE: usize = 4096;
 // 16 V Registers
 const NUM_V_REGS: usize = 16;
 const NUM_FLAG_REGS: usize = 8;
-const STACK_SIZE: usize = 16;
+pub const STACK_SIZE: usize = 16;
 // 16 key input
 const NUM_KEYS: usize = 16;
 
 pub struct Cpu {
     // 16-bit program counter
     pc: u16,
-    // RAM is in bytes (8 bits) of RAM_SIZE size
-    ram: [u8; RAM_SIZE],
-    // monochrome display only requires 1 bit values (boolean)
-    // for colors (i.e., black is false, white is true)
-    screen: Vec<bool>,
-    screen_width: usize,
-    screen_height: usize,
+    // RAM is in bytes (8 bits), sized for the variant
+    ram: Vec<u8>,
+    // monochrome display only requires 1 bit values
+    // for colors (i.e., black is 0, white is 1)
+    screen: Framebuffer,
     // 8-bit registers
     v_reg: [u8; NUM_V_REGS],
-    // 16-bit indexing register
-    i_reg: u16,
+    // indexing register, 16-bit but for MegaChip's 24-bit I
+    i_reg: u32,
     // 8 8-bit flag registers for instructions FX75 and FX85
     flag_reg: [u8; NUM_FLAG_REGS],
     // 16-bit stack pointer
@@ -80,6 +109,11 @@ pub struct Cpu {
     keys: [bool; NUM_KEYS],
     // previous keys array for use with FX0A instruction
     prev_keys: [bool; NUM_KEYS],
+    // CHIP-8X second keypad, color board and I/O port
+    keys2: [bool; NUM_KEYS],
+    color_board: ColorBoard,
+    io_port: IoPort,
+    megachip: MegaChip,
     // 8-bit delay timer register
     delay_t: u8,
     // 8-bit sound timer register
@@ -88,22 +122,53 @@ pub struct Cpu {
     variant: Chip8Variant,
     display_mode: DisplayMode,
     quirks: Quirks,
+    // address of the instruction currently being executed
+    op_addr: u16,
+    debugger: Debugger,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    // addresses of the first and last byte of the loaded ROM
+    rom_start: u16,
+    rom_end: u16,
+    // addresses load puts the ROM at and starts executing from
+    load_addr: u16,
+    start_addr: u16,
+    stack_policy: StackPolicy,
+    // set when the CPU halts on an error, tick does nothing until reset
+    error: Option<CpuError>,
+    // COSMAC VIP machine cycles executed, never reset
+    cycles: u64,
+    // set by tick_timers, DXYN waits for it with the display_wait quirk
+    vblank: bool,
+    // DXYN at PC is waiting for the next vblank, tick does nothing until then
+    display_waiting: bool,
+    // PC is still at the entry point, whose breakpoint is checked before
+    // the first instruction runs
+    at_entry: bool,
 }
 
-// starting address
-const START_ADDR: u16 = 0x200;
+// Snapshot of the CPU registers for debugging views
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Registers {
+    pub pc: u16,
+    pub i_reg: u32,
+    pub sp: u16,
+    pub v_reg: [u8; NUM_V_REGS],
+    pub delay_t: u8,
+    pub sound_t: u8,
+}
 
 impl Cpu {
     // Initalize CPU state
     pub fn new(audio: AudioManager, variant: Chip8Variant) -> Self {
         let quirks = Quirks::new_variant(variant);
+        let start_addr = config::start_address(variant);
 
         let mut new_cpu = Self {
-            pc: START_ADDR,
-            ram: [0; RAM_SIZE],
-            screen: vec![false; SCREEN_WIDTH * SCREEN_HEIGHT],
-            screen_width: SCREEN_WIDTH,
-            screen_height: SCREEN_HEIGHT,
+            pc: start_addr,
+            ram: vec![0; config::memory_size(variant)],
+            screen: Framebuffer::new(PixelFormat::Mono, SCREEN_WIDTH, SCREEN_HEIGHT),
             v_reg: [0; NUM_V_REGS],
             i_reg: 0,
             flag_reg: [0; NUM_FLAG_REGS],
@@ -111,14 +176,34 @@ impl Cpu {
             stack: [0; STACK_SIZE],
             keys: [false; NUM_KEYS],
             prev_keys: [false; NUM_KEYS],
+            keys2: [false; NUM_KEYS],
+            color_board: ColorBoard::new(),
+            io_port: IoPort::new(),
+            megachip: MegaChip::new(),
             delay_t: 0,
             sound_t: 0,
             audio,
             variant,
             display_mode: DisplayMode::LoRes,
             quirks,
+            op_addr: start_addr,
+            debugger: Debugger::new(),
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            rom_start: start_addr,
+            rom_end: start_addr,
+            load_addr: config::load_address(variant),
+            start_addr,
+            stack_policy: StackPolicy::Error,
+            error: None,
+            cycles: 0,
+            vblank: false,
+            display_waiting: false,
+            at_entry: true,
         };
 
+        new_cpu.clear_screen();
         new_cpu.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
         new_cpu.ram[0x100..0x100 + HIRES_FONTSET_SIZE].copy_from_slice(&HIRES_FONTSET);
 
@@ -127,11 +212,8 @@ impl Cpu {
 
     // Reset CPU state
     pub fn reset(&mut self) {
-        self.pc = START_ADDR;
-        self.ram = [0; RAM_SIZE];
-        self.screen = vec![false; SCREEN_WIDTH * SCREEN_HEIGHT];
-        self.screen_width = SCREEN_WIDTH;
-        self.screen_height = SCREEN_HEIGHT;
+        self.pc = self.start_addr;
+        self.ram.fill(0);
         self.v_reg = [0; NUM_V_REGS];
         self.i_reg = 0;
         self.flag_reg = [0; NUM_FLAG_REGS];
@@ -139,24 +221,181 @@ impl Cpu {
         self.stack = [0; STACK_SIZE];
         self.keys = [false; NUM_KEYS];
         self.prev_keys = [false; NUM_KEYS];
+        self.keys2 = [false; NUM_KEYS];
+        self.color_board = ColorBoard::new();
+        self.io_port = IoPort::new();
+        self.megachip = MegaChip::new();
+        self.audio.stop_sample();
         self.delay_t = 0;
         self.sound_t = 0;
         self.audio.stop_beep();
         self.display_mode = DisplayMode::LoRes;
+        self.clear_screen();
+        self.op_addr = self.start_addr;
+        self.error = None;
+        self.vblank = false;
+        self.display_waiting = false;
+        self.at_entry = true;
+        self.debugger.reset();
         self.ram[..FONTSET_SIZE].copy_from_slice(&FONTSET);
         self.ram[0x100..0x100 + HIRES_FONTSET_SIZE].copy_from_slice(&HIRES_FONTSET);
     }
 
     // Perform one CPU cycle (tick)
     pub fn tick(&mut self) {
+        if self.error.is_some() {
+            return;
+        }
+        // Breakpoints are otherwise checked once PC moves, which it hasn't
+        // yet for the first instruction
+        if self.at_entry {
+            self.at_entry = false;
+            self.debugger.on_pc(self.pc);
+            if self.debugger.has_break() {
+                return;
+            }
+        }
+        // Stall before a draw until the next vblank, without executing or
+        // counting anything
+        if self.quirks.display_wait
+            && !self.display_waiting
+            && self.peek_opcode() & 0xF000 == 0xD000
+        {
+            self.display_waiting = true;
+            self.vblank = false;
+        }
+        if self.display_waiting {
+            if !self.vblank {
+                return;
+            }
+            self.display_waiting = false;
+        }
+        self.op_addr = self.pc;
+        // Snapshot registers if this instruction is being traced
+        let before = match &self.tracer {
+            Some(tracer) if tracer.wants(self.pc) => Some(self.get_registers()),
+            _ => None,
+        };
         // Fetch
         let op = self.fetch();
+        let vx = self.v_reg[((op & 0x0F00) >> 8) as usize];
         // Decode and execute
         self.execute(op);
+        let skipped = self.pc == self.op_addr.wrapping_add(4);
+        self.cycles += timing::vip_cycles(op, vx, skipped) as u64;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(self.op_addr, op);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(self.op_addr, op, self.pc);
+        }
+        if let Some(before) = before {
+            let after = self.get_registers();
+            if let Some(tracer) = &mut self.tracer {
+                tracer.record(TraceEntry {
+                    opcode: op,
+                    variant: self.variant,
+                    before,
+                    after,
+                });
+            }
+        }
+        // Stop at breakpoints before the next instruction runs, but not
+        // again while an instruction repeats waiting for a key
+        if self.pc != self.op_addr {
+            self.debugger.on_pc(self.pc);
+        }
+    }
+
+    // Execute one instruction, finishing a draw that waits for vblank as if
+    // the vblank had come (used by debuggers to single step)
+    pub fn step(&mut self) {
+        self.tick();
+        if self.display_waiting {
+            self.vblank = true;
+            self.tick();
+        }
+    }
+
+    // Whether a draw is stalled until the next tick_timers call
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.display_waiting
+    }
+
+    // Return the error the CPU halted on, if any
+    pub fn error(&self) -> Option<CpuError> {
+        self.error
+    }
+
+    pub fn stack_policy(&self) -> StackPolicy {
+        self.stack_policy
+    }
+
+    // Also clears a stack error, so the faulting instruction is retried
+    // under the new policy
+    pub fn set_stack_policy(&mut self, policy: StackPolicy) {
+        self.stack_policy = policy;
+        self.error = None;
+    }
+
+    // Return the return addresses on the stack, outermost call first
+    pub fn get_call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    // Quirks start out as the variant's, and can be overridden for ROMs
+    // that expect otherwise (e.g. sprites wrapping around the screen edges)
+    pub fn quirks(&self) -> &Quirks {
+        &self.quirks
+    }
+
+    pub fn quirks_mut(&mut self) -> &mut Quirks {
+        &mut self.quirks
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
     }
 
-    // Decrement timers at ~60Hz
+    // Start tracing with the given tracer (or stop with None), returning the old one
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Start profiling with the given profiler (or stop with None), returning the old one
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler)
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Start recording coverage (or stop with None), returning the old recording
+    pub fn set_coverage(&mut self, coverage: Option<Coverage>) -> Option<Coverage> {
+        std::mem::replace(&mut self.coverage, coverage)
+    }
+
+    // Return the reason the last tick hit a breakpoint or watchpoint, if any
+    pub fn take_break(&mut self) -> Option<BreakReason> {
+        self.debugger.take_break()
+    }
+
+    // Decrement timers, called config::FRAME_RATE times per second
     pub fn tick_timers(&mut self) {
+        self.vblank = true;
         if self.delay_t > 0 {
             self.delay_t -= 1;
         }
@@ -170,44 +409,368 @@ impl Cpu {
         }
     }
 
-    // Return screen buffer and other relevant display information
-    pub fn get_display(&self) -> (&[bool], usize, usize, DisplayMode) {
+    // Return the screen on display, the MegaChip frame in MegaChip mode
+    pub fn get_framebuffer(&self) -> &Framebuffer {
+        match self.get_megachip() {
+            Some(megachip) => megachip.get_frame(),
+            None => &self.screen,
+        }
+    }
+
+    // Return the screen as RGBA, ready to upload as a texture. Mono pixels
+    // are colored from palette, unless the CHIP-8X color board colors them
+    pub fn get_rgba(&self, palette: &[u32]) -> Vec<u8> {
+        match self.get_color_board() {
+            Some(board) => board.colorize(&self.screen).to_rgba(&chip8x::PALETTE),
+            None => self.get_framebuffer().to_rgba(palette),
+        }
+    }
+
+    // Return the current resolution mode
+    pub fn get_display_mode(&self) -> DisplayMode {
+        self.display_mode
+    }
+
+    // Return the width and height of the screen in pixels of the current
+    // mode, which the screen buffer is a multiple of when LoRes is doubled
+    pub fn get_resolution(&self) -> (usize, usize) {
+        let scale = self.pixel_size();
         (
-            &self.screen,
-            self.screen_width,
-            self.screen_height,
-            self.display_mode,
+            self.screen.get_width() / scale,
+            self.screen.get_height() / scale,
         )
     }
 
+    // Return the variant's addressable memory
+    pub fn get_ram(&self) -> &[u8] {
+        &self.ram[..config::address_space(self.variant)]
+    }
+
+    // Return a snapshot of the registers
+    pub fn get_registers(&self) -> Registers {
+        Registers {
+            pc: self.pc,
+            i_reg: self.i_reg,
+            sp: self.sp,
+            v_reg: self.v_reg,
+            delay_t: self.delay_t,
+            sound_t: self.sound_t,
+        }
+    }
+
+    // Overwrite the registers (used by the GDB stub)
+    pub fn set_registers(&mut self, regs: Registers) {
+        // MegaChip's address space is all of u16, so reduce PC as a usize,
+        // and keep it where a whole opcode can be fetched
+        let space = config::address_space(self.variant);
+        let pc = (regs.pc as usize % space).min(space - 2) as u16;
+        // A draw waiting for vblank is abandoned when PC moves away from it
+        if pc != self.pc {
+            self.display_waiting = false;
+        }
+        self.pc = pc;
+        self.i_reg = regs.i_reg % config::memory_size(self.variant) as u32;
+        self.sp = regs.sp.min(STACK_SIZE as u16);
+        self.v_reg = regs.v_reg;
+        self.delay_t = regs.delay_t;
+        self.sound_t = regs.sound_t;
+    }
+
+    pub fn get_variant(&self) -> Chip8Variant {
+        self.variant
+    }
+
+    // Return the COSMAC VIP machine cycles executed so far
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Return the addresses of the first and last byte of the loaded ROM
+    pub fn get_rom_bounds(&self) -> (u16, u16) {
+        (self.rom_start, self.rom_end)
+    }
+
+    // Return the opcode at PC without executing it, reading zeroes past the
+    // end of memory
+    pub fn peek_opcode(&self) -> u16 {
+        let byte = |addr: usize| self.ram.get(addr).copied().unwrap_or(0) as u16;
+        let pc = self.pc as usize;
+        byte(pc) << 8 | byte(pc + 1)
+    }
+
+    // Overwrite a single byte of memory (used by the memory editor)
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        if (addr as usize) < config::address_space(self.variant) {
+            self.ram[addr as usize] = val;
+        }
+    }
+
     pub fn keypress(&mut self, idx: usize, pressed: bool) {
         // Track key state changes for FX0A
         self.prev_keys[idx] = self.keys[idx];
         self.keys[idx] = pressed;
     }
 
-    // Load external ROM data starting at 0x200
-    pub fn load(&mut self, data: &[u8]) {
-        let start = START_ADDR as usize;
-        let end = (START_ADDR as usize) + data.len();
+    // Press or release a key of the CHIP-8X second keypad
+    pub fn keypress2(&mut self, idx: usize, pressed: bool) {
+        self.keys2[idx] = pressed;
+    }
+
+    // Return the color board, only CHIP-8X has one
+    pub fn get_color_board(&self) -> Option<&ColorBoard> {
+        (self.variant == Chip8Variant::Chip8X).then_some(&self.color_board)
+    }
+
+    // Return the MegaChip state while in MegaChip mode
+    pub fn get_megachip(&self) -> Option<&MegaChip> {
+        (self.display_mode == DisplayMode::MegaChip).then_some(&self.megachip)
+    }
+
+    // Return the stand-in for the CHIP-8X I/O port
+    pub fn io_port_mut(&mut self) -> &mut IoPort {
+        &mut self.io_port
+    }
+
+    // Set the address load puts the ROM at, 0x200 for most variants
+    pub fn set_load_addr(&mut self, addr: u16) {
+        self.load_addr = addr;
+    }
+
+    // Set the address load and reset start executing from, 0x200 for most
+    // variants
+    pub fn set_start_addr(&mut self, addr: u16) {
+        self.start_addr = addr;
+    }
+
+    // Return the addresses load puts the ROM at and starts executing from
+    pub fn get_entry(&self) -> (u16, u16) {
+        (self.load_addr, self.start_addr)
+    }
+
+    // Load external ROM data at the load address and begin execution at the
+    // start address
+    pub fn load(&mut self, data: &[u8]) -> Result<(), LoadError> {
+        self.load_at(self.load_addr, data)?;
+        self.pc = self.start_addr;
+        Ok(())
+    }
+
+    // Load external ROM data starting at addr and begin execution there
+    pub fn load_at(&mut self, addr: u16, data: &[u8]) -> Result<(), LoadError> {
+        let ram_size = config::memory_size(self.variant);
+        let start = addr as usize;
+
+        if data.is_empty() {
+            return Err(LoadError::Empty);
+        }
+        if start >= ram_size {
+            return Err(LoadError::InvalidAddress { addr, ram_size });
+        }
+        if data.len() > ram_size - start {
+            return Err(LoadError::TooLarge {
+                size: data.len(),
+                max_size: ram_size - start,
+            });
+        }
+
+        let end = start + data.len();
         self.ram[start..end].copy_from_slice(data);
+        self.pc = addr;
+        self.at_entry = true;
+        self.display_waiting = false;
+        self.rom_start = addr;
+        // MegaChip ROMs can run past the address space with data
+        self.rom_end = (end - 1).min(u16::MAX as usize) as u16;
+
+        Ok(())
     }
 
-    fn push(&mut self, val: u16) {
-        self.stack[self.sp as usize] = val;
-        self.sp += 1;
+    // Push a return address, returning false if the call can't go ahead
+    fn push(&mut self, val: u16) -> bool {
+        let sp = self.sp as usize;
+        if sp >= STACK_SIZE && self.stack_policy != StackPolicy::Wrap {
+            self.stack_fault(
+                BreakReason::StackOverflow(self.op_addr),
+                CpuError::StackOverflow { pc: self.op_addr },
+            );
+            return false;
+        }
+
+        let idx = sp % STACK_SIZE;
+        self.stack[idx] = val;
+        self.sp = (idx + 1) as u16;
+        true
     }
 
-    fn pop(&mut self) -> u16 {
+    // Pop a return address, returning None if the return can't go ahead
+    fn pop(&mut self) -> Option<u16> {
+        if self.sp == 0 {
+            if self.stack_policy != StackPolicy::Wrap {
+                self.stack_fault(
+                    BreakReason::StackUnderflow(self.op_addr),
+                    CpuError::StackUnderflow { pc: self.op_addr },
+                );
+                return None;
+            }
+            self.sp = STACK_SIZE as u16;
+        }
+
         self.sp -= 1;
-        self.stack[self.sp as usize]
+        Some(self.stack[self.sp as usize])
+    }
+
+    // Handle a stack overflow or underflow according to the stack policy
+    fn stack_fault(&mut self, reason: BreakReason, error: CpuError) {
+        // Leave PC on the offending instruction, so it runs again on resume
+        self.pc = self.op_addr;
+        match self.stack_policy {
+            StackPolicy::Error => self.error = Some(error),
+            StackPolicy::Break => self.debugger.on_break(reason),
+            StackPolicy::Wrap => {}
+        }
+    }
+
+    // Leave I where the variant's FX55 and FX65 leave it
+    fn increment_i(&mut self, x: usize) {
+        match self.quirks.memory {
+            MemoryIncrement::XPlusOne => self.i_reg += x as u32 + 1,
+            MemoryIncrement::X => self.i_reg += x as u32,
+            MemoryIncrement::Unchanged => {}
+        }
+    }
+
+    // Screen buffer pixels to scroll by for a scroll of distance pixels
+    fn scroll_distance(&self, distance: usize) -> usize {
+        let distance = distance * self.pixel_size();
+        if self.quirks.lores_half_scroll && self.display_mode == DisplayMode::LoRes {
+            distance / 2
+        } else {
+            distance
+        }
+    }
+
+    // Scroll the screen by (dx, dy) screen buffer pixels
+    fn scroll_screen(&mut self, dx: isize, dy: isize) {
+        if self.display_mode == DisplayMode::MegaChip {
+            self.megachip.scroll(dx, dy);
+        } else {
+            self.screen.scroll(dx, dy);
+        }
+    }
+
+    // DXYN in MegaChip mode. Font sprites are drawn from their bits like
+    // in the other modes, anything else from sprite width x height palette
+    // indices at I
+    fn draw_megachip_sprite(&mut self, x: usize, y: usize, n: u16) {
+        let font = self.i_reg < config::load_address(self.variant) as u32;
+        let (width, pixels) = if font {
+            let (rows, cols) = if n == 0 { (16, 16) } else { (n as u32, 8) };
+            let row_bytes = cols / 8;
+            let mut pixels = Vec::new();
+            for row in 0..rows {
+                let mut bits = 0;
+                for byte in 0..row_bytes {
+                    let addr = self.i_reg + row * row_bytes + byte;
+                    bits = bits << 8 | self.read_mem(addr, AccessSource::Draw) as u32;
+                }
+                pixels.extend((0..cols).rev().map(|col| (bits >> col) as u8 & 1));
+            }
+            (cols as usize, pixels)
+        } else {
+            let (width, height) = self.megachip.get_sprite_size();
+            let pixels = (0..(width * height) as u32)
+                .map(|offset| self.read_mem(self.i_reg + offset, AccessSource::Draw))
+                .collect();
+            (width, pixels)
+        };
+
+        let (vx, vy) = (self.v_reg[x] as usize, self.v_reg[y] as usize);
+        let collided = self.megachip.draw_sprite(vx, vy, width, &pixels, font);
+        self.v_reg[0xF] = collided as u8;
+    }
+
+    // Size of the screen buffer in a display mode
+    fn buffer_size(&self, mode: DisplayMode) -> (usize, usize) {
+        match mode {
+            DisplayMode::LoRes if !self.quirks.lores_doubling => config::lores_size(self.variant),
+            DisplayMode::MegaChip => (MEGA_WIDTH, MEGA_HEIGHT),
+            _ => (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT),
+        }
+    }
+
+    // Width and height of a pixel of the current mode on the screen buffer
+    fn pixel_size(&self) -> usize {
+        if self.quirks.lores_doubling && self.display_mode == DisplayMode::LoRes {
+            2
+        } else {
+            1
+        }
+    }
+
+    // Blank the screen buffer, sized for the current mode
+    fn clear_screen(&mut self) {
+        let (width, height) = self.buffer_size(self.display_mode);
+        self.screen = Framebuffer::new(PixelFormat::Mono, width, height);
+    }
+
+    // Switch resolution, scaling the picture to the new one unless the
+    // variant clears the screen
+    fn set_display_mode(&mut self, mode: DisplayMode) {
+        let (width, height) = self.buffer_size(mode);
+        self.screen = if self.quirks.mode_switch_clear {
+            Framebuffer::new(PixelFormat::Mono, width, height)
+        } else {
+            self.screen.resized(PixelFormat::Mono, width, height)
+        };
+        self.display_mode = mode;
+    }
+
+    // Move PC to addr, wrapping around the variant's address space like
+    // the 12 or 16-bit PC of the original interpreters
+    fn jump(&mut self, addr: usize) {
+        self.pc = (addr % config::address_space(self.variant)) as u16;
+    }
+
+    // Skip the next instruction
+    fn skip(&mut self) {
+        self.jump(self.pc as usize + 2);
+    }
+
+    // Read a byte of RAM, reporting the access to the debugger. Addresses
+    // past the end of memory (e.g. I plus an offset) wrap around to 0
+    fn read_mem(&mut self, addr: u32, source: AccessSource) -> u8 {
+        let addr = addr % self.ram.len() as u32;
+        let value = self.ram[addr as usize];
+        self.report_access(addr, value, AccessKind::Read, source);
+        value
+    }
+
+    // Write a byte of RAM, reporting the access to the debugger, wrapping
+    // like read_mem
+    fn write_mem(&mut self, addr: u32, value: u8, source: AccessSource) {
+        let addr = addr % self.ram.len() as u32;
+        self.ram[addr as usize] = value;
+        self.report_access(addr, value, AccessKind::Write, source);
+    }
+
+    // The debugger only watches the 16-bit address space
+    fn report_access(&mut self, addr: u32, value: u8, kind: AccessKind, source: AccessSource) {
+        if let Ok(addr) = u16::try_from(addr) {
+            self.debugger.on_access(MemAccess {
+                pc: self.op_addr,
+                addr,
+                value,
+                kind,
+                source,
+            });
+        }
     }
 
     fn fetch(&mut self) -> u16 {
-        let first_byte = self.ram[self.pc as usize] as u16;
-        let second_byte = self.ram[(self.pc + 1) as usize] as u16;
+        let first_byte = self.read_mem(self.pc as u32, AccessSource::Fetch) as u16;
+        let second_byte = self.read_mem(self.pc as u32 + 1, AccessSource::Fetch) as u16;
         let op = (first_byte << 8) | second_byte;
-        self.pc += 2;
+        self.skip();
         op
     }
 
@@ -223,74 +786,125 @@ impl Cpu {
 
         match digit_1 {
             0x0 => match (digit_2, digit_3, digit_4) {
-                // 0XCN - Scroll display down N pixels
+                // 00BN - Scroll display up N pixels in MegaChip
+                (0x0, 0xB, _) => {
+                    if self.variant == Chip8Variant::MegaChip {
+                        let rows_to_scroll = self.scroll_distance(digit_4 as usize);
+                        self.scroll_screen(0, -(rows_to_scroll as isize));
+                    } else {
+                        panic!("invalid opcode")
+                    }
+                }
+                // 00CN - Scroll display down N pixels
                 (0x0, 0xC, _) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        let rows_to_scroll = digit_4 as usize;
-                        for y in (0..self.screen_height - rows_to_scroll).rev() {
-                            for x in 0..self.screen_width {
-                                let from = x + self.screen_width * y;
-                                let to = x + self.screen_width * (y + rows_to_scroll);
-                                self.screen[to] = self.screen[from];
-                            }
-                        }
-
-                        for y in 0..rows_to_scroll {
-                            for x in 0..self.screen_width {
-                                let idx = x + self.screen_width * y;
-                                self.screen[idx] = false;
-                            }
-                        }
+                    if self.variant.can_scroll() {
+                        let rows_to_scroll = self.scroll_distance(digit_4 as usize);
+                        self.scroll_screen(0, rows_to_scroll as isize);
                     } else {
                         panic!("invalid opcode")
                     }
                 }
+                // 0010 - Disable MegaChip mode
+                (0x0, 0x1, 0x0) if self.variant == Chip8Variant::MegaChip => {
+                    self.set_display_mode(DisplayMode::LoRes);
+                }
+                // 0011 - Enable MegaChip mode
+                (0x0, 0x1, 0x1) if self.variant == Chip8Variant::MegaChip => {
+                    self.set_display_mode(DisplayMode::MegaChip);
+                }
+                // 00E0 - Clear screen, in MegaChip mode show the frame drawn
+                // since the last one
+                (0x0, 0xE, 0x0) if self.display_mode == DisplayMode::MegaChip => {
+                    self.megachip.present();
+                }
                 // 00E0 - Clear screen
                 (0x0, 0xE, 0x0) => {
-                    self.screen = vec![false; self.screen_width * self.screen_height];
+                    self.clear_screen();
+                }
+                // 01NN NNNN - I = NNNNNN in MegaChip, taking the next word too
+                (0x1, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    let high = self.read_mem(self.pc as u32, AccessSource::Fetch) as u32;
+                    let low = self.read_mem(self.pc as u32 + 1, AccessSource::Fetch) as u32;
+                    self.skip();
+                    self.i_reg = (nn as u32) << 16 | high << 8 | low;
+                }
+                // 02NN - Load NN palette colors from I in MegaChip
+                (0x2, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    let colors: Vec<u8> = (0..nn as u32 * 4)
+                        .map(|offset| self.read_mem(self.i_reg + offset, AccessSource::Load))
+                        .collect();
+                    self.megachip.load_palette(&colors);
+                }
+                // 03NN - Set sprite width to NN in MegaChip
+                (0x3, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    self.megachip.set_sprite_width(nn);
+                }
+                // 04NN - Set sprite height to NN in MegaChip
+                (0x4, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    self.megachip.set_sprite_height(nn);
+                }
+                // 05NN - Set screen alpha to NN in MegaChip
+                (0x5, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    self.megachip.set_screen_alpha(nn);
+                }
+                // 060N - Play sound at I in MegaChip, looped if N is 0
+                (0x6, 0x0, _) if self.variant == Chip8Variant::MegaChip => {
+                    let sound = self.ram.get(self.i_reg as usize..).unwrap_or_default();
+                    if let Some(wav) = megachip::sound_to_wav(sound) {
+                        self.audio.play_sample(&wav, digit_4 == 0);
+                    }
+                }
+                // 0700 - Stop sound in MegaChip
+                (0x7, 0x0, 0x0) if self.variant == Chip8Variant::MegaChip => {
+                    self.audio.stop_sample();
+                }
+                // 080N - Set sprite blend mode in MegaChip
+                (0x8, 0x0, _) if self.variant == Chip8Variant::MegaChip => {
+                    match BlendMode::from_index(digit_4 as u8) {
+                        Some(mode) => self.megachip.set_blend_mode(mode),
+                        None => panic!("invalid opcode"),
+                    }
+                }
+                // 09NN - Set collision color to NN in MegaChip
+                (0x9, _, _) if self.variant == Chip8Variant::MegaChip => {
+                    self.megachip.set_collision_color(nn);
+                }
+                // 02A0 - Cycle background color in CHIP-8X
+                (0x2, 0xA, 0x0) => {
+                    if self.variant == Chip8Variant::Chip8X {
+                        self.color_board.cycle_background();
+                    } else {
+                        panic!("invalid opcode")
+                    }
+                }
+                // 0230 - Clear screen in HIRES CHIP-8
+                (0x2, 0x3, 0x0) => {
+                    if self.variant == Chip8Variant::HiresChip8 {
+                        self.clear_screen();
+                    } else {
+                        panic!("invalid opcode")
+                    }
                 }
                 // 00EE - Return from subroutine
                 (0x0, 0xE, 0xE) => {
-                    self.pc = self.pop();
+                    if let Some(addr) = self.pop() {
+                        self.pc = addr;
+                    }
                 }
                 // 00FB - Scroll display right 4 pixels
                 (0x0, 0xF, 0xB) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        for x in (0..self.screen_width - 4).rev() {
-                            for y in 0..self.screen_height {
-                                let from = x + self.screen_width * y;
-                                let to = x + 4 + self.screen_width * y;
-                                self.screen[to] = self.screen[from];
-                            }
-                        }
-
-                        for x in 0..4 {
-                            for y in 0..self.screen_height {
-                                let idx = x + self.screen_width * y;
-                                self.screen[idx] = false;
-                            }
-                        }
+                    if self.variant.can_scroll() {
+                        let cols_to_scroll = self.scroll_distance(4);
+                        self.scroll_screen(cols_to_scroll as isize, 0);
                     } else {
                         panic!("invalid opcode")
                     }
                 }
                 // 00FC - Scroll display left 4 pixels
                 (0x0, 0xF, 0xC) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        for x in 4..self.screen_width {
-                            for y in 0..self.screen_height {
-                                let from = x + self.screen_width * y;
-                                let to = x - 4 + self.screen_width * y;
-                                self.screen[to] = self.screen[from];
-                            }
-                        }
-
-                        for x in (self.screen_width - 4)..self.screen_width {
-                            for y in 0..self.screen_height {
-                                let idx = x + self.screen_width * y;
-                                self.screen[idx] = false;
-                            }
-                        }
+                    if self.variant.can_scroll() {
+                        let cols_to_scroll = self.scroll_distance(4);
+                        self.scroll_screen(-(cols_to_scroll as isize), 0);
                     } else {
                         panic!("invalid opcode")
                     }
@@ -299,22 +913,16 @@ impl Cpu {
                 (0x0, 0xF, 0xD) => std::process::exit(0),
                 // 00FE - Disable HiRes Graphics Mode
                 (0x0, 0xF, 0xE) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        self.display_mode = DisplayMode::LoRes;
-                        self.screen_width = 64;
-                        self.screen_height = 32;
-                        self.screen = vec![false; self.screen_width * self.screen_height];
+                    if self.variant.is_superchip() {
+                        self.set_display_mode(DisplayMode::LoRes);
                     } else {
                         panic!("invalid opcode")
                     }
                 }
                 // 00FF - Enable HiRes Graphics Mode
                 (0x0, 0xF, 0xF) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        self.display_mode = DisplayMode::HiRes;
-                        self.screen_width = 128;
-                        self.screen_height = 64;
-                        self.screen = vec![false; self.screen_width * self.screen_height];
+                    if self.variant.is_superchip() {
+                        self.set_display_mode(DisplayMode::HiRes);
                     } else {
                         panic!("invalid opcode")
                     }
@@ -327,28 +935,34 @@ impl Cpu {
             }
             0x2 => {
                 // 2NNN - Call subroutine
-                self.push(self.pc);
-                self.pc = nnn;
+                if self.push(self.pc) {
+                    self.pc = nnn;
+                }
             }
             0x3 => {
                 // 3XNN - Skip next if VX == NN
                 if self.v_reg[x] == nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             0x4 => {
                 // 4XNN - Skip next if VX != NN
                 if self.v_reg[x] != nn {
-                    self.pc += 2;
+                    self.skip();
                 }
             }
             0x5 => match digit_4 {
                 // 5XY0 - Skip next if VX == VY
                 0x0 => {
                     if self.v_reg[x] == self.v_reg[y] {
-                        self.pc += 2;
+                        self.skip();
                     }
                 }
+                // 5XY1 - VX += VY for each nibble separately, modulo 8, in CHIP-8X
+                0x1 if self.variant == Chip8Variant::Chip8X => {
+                    let sum = (self.v_reg[x] & 0x77) + (self.v_reg[y] & 0x77);
+                    self.v_reg[x] = sum & 0x77;
+                }
                 _ => panic!("invalid opcode"),
             },
             0x6 => {
@@ -433,22 +1047,35 @@ impl Cpu {
                 // 9XY0 - Skip next if VX != VY
                 0x0 => {
                     if self.v_reg[x] != self.v_reg[y] {
-                        self.pc += 2;
+                        self.skip();
                     }
                 }
                 _ => panic!("invalid opcode"),
             },
             0xA => {
                 // ANNN - I = NNN
-                self.i_reg = nnn;
+                self.i_reg = nnn as u32;
+            }
+            0xB if self.variant == Chip8Variant::Chip8X => {
+                let vx = self.v_reg[x];
+                let vx1 = self.v_reg[(x + 1) % NUM_V_REGS];
+                let vy = self.v_reg[y];
+                if digit_4 == 0 {
+                    // BXY0 - Color blocks at VX, VX+1 with color VY
+                    self.color_board.fill_blocks(vx, vx1, vy);
+                } else {
+                    // BXYN - Color N rows from (VX, VX+1) with color VY
+                    self.color_board.fill_rows(vx, vx1, digit_4 as u8, vy);
+                }
             }
             0xB => {
                 // BNNN - Jump to V0 + NNN
-                if self.quirks.jumping {
-                    self.pc = (self.v_reg[x] as u16) + nnn;
+                let offset = if self.quirks.jumping {
+                    self.v_reg[x]
                 } else {
-                    self.pc = (self.v_reg[0] as u16) + nnn;
-                }
+                    self.v_reg[0]
+                };
+                self.jump(offset as usize + nnn as usize);
             }
             0xC => {
                 // CXNN - rand() & NN
@@ -459,44 +1086,66 @@ impl Cpu {
                 // DXYN - Draw 8xN Sprite
                 // DXY0 - Draw 16x16 Sprite in HiRes Mode
 
+                if self.display_mode == DisplayMode::MegaChip {
+                    self.draw_megachip_sprite(x, y, digit_4);
+                    return;
+                }
+
                 // Get (x, y) coords for sprite, wrap before drawing.
-                let x_coord = self.v_reg[digit_2 as usize] as u16 % self.screen_width as u16;
-                let y_coord = self.v_reg[digit_3 as usize] as u16 % self.screen_height as u16;
-                // If in HiRes Mode, with a N value of 0, then draw 16x16 sprite
+                let (width, height) = self.get_resolution();
+                let (width, height) = (width as u16, height as u16);
+                let x_coord = self.v_reg[digit_2 as usize] as u16 % width;
+                let y_coord = self.v_reg[digit_3 as usize] as u16 % height;
+                // With a N value of 0, draw a 16x16 sprite in HiRes Mode and
+                // whatever the variant draws in LoRes Mode
                 // Else, draw 8xN sprite with N (digit_4) height
-                let (num_rows, num_cols) =
-                    if self.display_mode == DisplayMode::HiRes && digit_4 == 0x0 {
-                        (16, 16)
-                    } else {
-                        (digit_4, 8)
-                    };
+                let lores_dxy0 = if self.display_mode == DisplayMode::HiRes {
+                    LoResDxy0::Sprite16x16
+                } else {
+                    self.quirks.lores_dxy0
+                };
+                let (num_rows, num_cols) = match lores_dxy0 {
+                    LoResDxy0::Sprite8x16 if digit_4 == 0x0 => (16, 8),
+                    LoResDxy0::Sprite16x16 if digit_4 == 0x0 => (16, 16),
+                    _ => (digit_4, 8),
+                };
                 // Keep track if any pixels were flipped
                 let mut flipped = false;
+                // and how many rows flipped a pixel or were clipped
+                let mut collided_rows = 0;
                 // Iterate over each row of the sprite
                 for y_line in 0..num_rows {
-                    let y = y_coord + y_line;
-                    if y >= self.screen_height as u16 {
-                        continue; // Clip bottom
+                    let mut y = y_coord + y_line;
+                    if y >= height {
+                        if self.quirks.clipping {
+                            collided_rows += 1;
+                            continue; // Clip bottom
+                        }
+                        y %= height; // Wrap to top
                     }
 
                     // Determine where row's data is stored
                     let addr = if num_cols == 16 {
-                        (self.i_reg + y_line * 2) as u16
+                        self.i_reg + y_line as u32 * 2
                     } else {
-                        (self.i_reg + y_line) as u16
+                        self.i_reg + y_line as u32
                     };
                     let pixels = if num_cols == 16 {
-                        let first_byte = self.ram[addr as usize];
-                        let second_byte = self.ram[(addr + 1) as usize];
+                        let first_byte = self.read_mem(addr, AccessSource::Draw);
+                        let second_byte = self.read_mem(addr + 1, AccessSource::Draw);
                         (first_byte as u16) << 8 | (second_byte as u16)
                     } else {
-                        self.ram[addr as usize] as u16
+                        self.read_mem(addr, AccessSource::Draw) as u16
                     };
+                    let mut row_flipped = false;
                     // Iterate over column in current row
                     for x_line in 0..num_cols {
-                        let x = x_coord + x_line;
-                        if x >= self.screen_width as u16 {
-                            continue; // Clip right
+                        let mut x = x_coord + x_line;
+                        if x >= width {
+                            if self.quirks.clipping {
+                                continue; // Clip right
+                            }
+                            x %= width; // Wrap to left
                         }
 
                         // Use mask to fetch current pixel's bit. Flip if a 1
@@ -507,17 +1156,31 @@ impl Cpu {
                         };
 
                         if bit {
-                            // Get pixel's index for the 1D screen array
-                            let idx = x as usize + self.screen_width * y as usize;
-                            // Check if pixel will be flipped and set
-                            flipped |= self.screen[idx];
-                            self.screen[idx] ^= true;
+                            // Flip every screen buffer pixel the pixel covers
+                            let scale = self.pixel_size();
+                            for block_y in 0..scale {
+                                for block_x in 0..scale {
+                                    // Get pixel's position in the screen buffer
+                                    let px = x as usize * scale + block_x;
+                                    let py = y as usize * scale + block_y;
+                                    // Check if pixel will be flipped and set
+                                    let on = self.screen.get(px, py) != 0;
+                                    row_flipped |= on;
+                                    self.screen.set(px, py, !on as u32);
+                                }
+                            }
                         }
                     }
+                    flipped |= row_flipped;
+                    collided_rows += row_flipped as u8;
                 }
 
                 // Populate VF register
-                self.v_reg[0xF] = flipped as u8;
+                if self.quirks.collision_rows && self.display_mode == DisplayMode::HiRes {
+                    self.v_reg[0xF] = collided_rows;
+                } else {
+                    self.v_reg[0xF] = flipped as u8;
+                }
             }
             0xE => match (digit_3, digit_4) {
                 // EX9E - Skip if Key Pressed
@@ -525,7 +1188,7 @@ impl Cpu {
                     let vx = self.v_reg[x];
                     let key = self.keys[vx as usize];
                     if key {
-                        self.pc += 2;
+                        self.skip();
                     }
                 }
                 // EXA1 - Skip if Key Not Pressed
@@ -533,7 +1196,21 @@ impl Cpu {
                     let vx = self.v_reg[x];
                     let key = self.keys[vx as usize];
                     if !key {
-                        self.pc += 2;
+                        self.skip();
+                    }
+                }
+                // EXF2 - Skip if Key Pressed on the second keypad in CHIP-8X
+                (0xF, 0x2) if self.variant == Chip8Variant::Chip8X => {
+                    let vx = self.v_reg[x];
+                    if self.keys2[vx as usize & 0xF] {
+                        self.skip();
+                    }
+                }
+                // EXF5 - Skip if Key Not Pressed on the second keypad in CHIP-8X
+                (0xF, 0x5) if self.variant == Chip8Variant::Chip8X => {
+                    let vx = self.v_reg[x];
+                    if !self.keys2[vx as usize & 0xF] {
+                        self.skip();
                     }
                 }
                 _ => panic!("invalid opcode"),
@@ -556,7 +1233,7 @@ impl Cpu {
 
                     if !released {
                         // Redo opcode
-                        self.pc -= 2;
+                        self.pc = self.op_addr;
                     }
                 }
                 // FX15 - DT = VX
@@ -569,19 +1246,19 @@ impl Cpu {
                 }
                 // FX1E - I += VX
                 (0x1, 0xE) => {
-                    let vx = self.v_reg[x] as u16;
+                    let vx = self.v_reg[x] as u32;
                     self.i_reg = self.i_reg.wrapping_add(vx);
                 }
                 // FX29 - Set I to Sprite for Digit VX
                 (0x2, 0x9) => {
-                    let char = self.v_reg[x] as u16;
+                    let char = self.v_reg[x] as u32;
                     self.i_reg = char * 5;
                 }
                 // FX30 - Set I to HiRes Sprite for Digit VX (0 - 9)
                 (0x3, 0x0) => {
-                    if self.variant == Chip8Variant::SuperChip {
-                        let char = self.v_reg[x] as u16;
-                        self.i_reg = 0x100 + (char * 10) as u16;
+                    if self.variant.is_superchip() {
+                        let char = self.v_reg[x] as u32;
+                        self.i_reg = 0x100 + char * 10;
                     } else {
                         panic!("invalid opcode")
                     }
@@ -597,36 +1274,36 @@ impl Cpu {
                     // Get the ones digit of VX
                     let ones = vx % 10;
 
-                    self.ram[self.i_reg as usize] = hundreds;
-                    self.ram[(self.i_reg + 1) as usize] = tens;
-                    self.ram[(self.i_reg + 2) as usize] = ones;
+                    self.write_mem(self.i_reg, hundreds, AccessSource::Bcd);
+                    self.write_mem(self.i_reg + 1, tens, AccessSource::Bcd);
+                    self.write_mem(self.i_reg + 2, ones, AccessSource::Bcd);
                 }
                 // FX55 - Store V0 to VX into I
                 (0x5, 0x5) => {
-                    if self.quirks.memory {
-                        for idx in 0..=x {
-                            self.ram[self.i_reg as usize] = self.v_reg[idx];
-                            self.i_reg += 1;
-                        }
-                    } else {
-                        for idx in 0..=x {
-                            self.ram[self.i_reg as usize + idx] = self.v_reg[idx];
-                        }
+                    for idx in 0..=x {
+                        let addr = self.i_reg + idx as u32;
+                        self.write_mem(addr, self.v_reg[idx], AccessSource::Store);
                     }
+                    self.increment_i(x);
                 }
                 // FX65 - Load I into V0 to VX
                 (0x6, 0x5) => {
-                    if self.quirks.memory {
-                        for idx in 0..=x {
-                            self.v_reg[idx] = self.ram[self.i_reg as usize];
-                            self.i_reg += 1;
-                        }
-                    } else {
-                        for idx in 0..=x {
-                            self.v_reg[idx] = self.ram[self.i_reg as usize + idx];
-                        }
+                    for idx in 0..=x {
+                        let addr = self.i_reg + idx as u32;
+                        self.v_reg[idx] = self.read_mem(addr, AccessSource::Load);
                     }
+                    self.increment_i(x);
                 }
+                // FXF8 - Output VX to the I/O port in CHIP-8X
+                (0xF, 0x8) if self.variant == Chip8Variant::Chip8X => {
+                    self.io_port.write(self.v_reg[x]);
+                }
+                // FXFB - Wait for input from the I/O port into VX in CHIP-8X
+                (0xF, 0xB) if self.variant == Chip8Variant::Chip8X => match self.io_port.read() {
+                    Some(value) => self.v_reg[x] = value,
+                    // Redo opcode
+                    None => self.pc = self.op_addr,
+                },
                 // FX75 - Store V0 to VX into Flag Registers
                 (0x7, 0x5) => {
                     if x <= 7 {
@@ -649,3 +1326,306 @@ impl Cpu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // CPU of the variant with rom loaded, before its first instruction
+    fn cpu_with_rom(variant: Chip8Variant, rom: &[u8]) -> Cpu {
+        let mut cpu = Cpu::new(AudioManager::silent(), variant);
+        cpu.load(rom).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn breakpoint_on_entry_stops_before_first_instruction() {
+        // 6005 - V0 = 5
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &[0x60, 0x05]);
+        cpu.debugger_mut().add_breakpoint(0x200);
+
+        cpu.tick();
+        assert_eq!(cpu.take_break(), Some(BreakReason::Breakpoint(0x200)));
+        assert_eq!(cpu.get_registers().pc, 0x200);
+
+        cpu.tick();
+        assert_eq!(cpu.take_break(), None);
+        assert_eq!(cpu.get_registers().v_reg[0], 5);
+    }
+
+    // A000 D001: draw the top row of the 0 glyph at (0, 0), then 1204 loops
+    const DRAW_ROM: [u8; 6] = [0xA0, 0x00, 0xD0, 0x01, 0x12, 0x04];
+
+    #[test]
+    fn display_wait_stalls_without_executing() {
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &DRAW_ROM);
+        cpu.set_profiler(Some(Profiler::new(cpu.get_ram().len())));
+        cpu.tick();
+        let cycles = cpu.get_cycles();
+
+        for _ in 0..10 {
+            cpu.tick();
+        }
+        assert!(cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.get_registers().pc, 0x202);
+        assert_eq!(cpu.get_cycles(), cycles);
+        assert_eq!(cpu.profiler().unwrap().total(), 1);
+        assert_eq!(cpu.get_framebuffer().get(0, 0), 0);
+
+        cpu.tick_timers();
+        cpu.tick();
+        assert!(!cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.get_registers().pc, 0x204);
+        assert_eq!(cpu.profiler().unwrap().total(), 2);
+        assert_ne!(cpu.get_framebuffer().get(0, 0), 0);
+    }
+
+    #[test]
+    fn display_wait_needs_a_vblank_after_the_draw_is_reached() {
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &DRAW_ROM);
+        cpu.tick_timers();
+        cpu.tick();
+        cpu.tick();
+        assert!(cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.get_registers().pc, 0x202);
+    }
+
+    #[test]
+    fn step_completes_a_waiting_draw() {
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &DRAW_ROM);
+        cpu.step();
+        cpu.step();
+        assert!(!cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.get_registers().pc, 0x204);
+        assert_ne!(cpu.get_framebuffer().get(0, 0), 0);
+    }
+
+    #[test]
+    fn draws_without_display_wait_run_immediately() {
+        let mut cpu = cpu_with_rom(Chip8Variant::SuperChipModern, &DRAW_ROM);
+        cpu.tick();
+        cpu.tick();
+        assert!(!cpu.is_waiting_for_vblank());
+        assert_eq!(cpu.get_registers().pc, 0x204);
+    }
+
+    // Run a ROM that draws one sprite across the bottom right corner, up to
+    // the jump to itself it ends with
+    fn draw_across_corner(rom: &[u8], clipping: bool) -> Cpu {
+        let mut cpu = cpu_with_rom(Chip8Variant::SuperChipModern, rom);
+        cpu.quirks_mut().clipping = clipping;
+        while cpu.peek_opcode() != 0x1000 | cpu.get_registers().pc {
+            cpu.tick();
+        }
+        cpu
+    }
+
+    // 603C 611E A20A D014: 8x4 sprite at (60, 30) on the 64x32 screen
+    const LORES_CORNER_ROM: [u8; 14] = [
+        0x60, 0x3C, 0x61, 0x1E, 0xA2, 0x0A, 0xD0, 0x14, 0x12, 0x08, 0xFF, 0xFF, 0xFF, 0xFF,
+    ];
+
+    // 00FF 6078 613C A20C D010: 16x16 sprite at (120, 60) on the 128x64 screen
+    fn hires_corner_rom() -> Vec<u8> {
+        let mut rom = vec![
+            0x00, 0xFF, 0x60, 0x78, 0x61, 0x3C, 0xA2, 0x0C, 0xD0, 0x10, 0x12, 0x0A,
+        ];
+        rom.extend([0xFF; 32]);
+        rom
+    }
+
+    #[test]
+    fn lores_sprites_clip_at_right_and_bottom_edges() {
+        let cpu = draw_across_corner(&LORES_CORNER_ROM, true);
+        let screen = cpu.get_framebuffer();
+        assert_ne!(screen.get(60, 30), 0);
+        assert_ne!(screen.get(63, 31), 0);
+        assert_eq!(screen.get(0, 30), 0);
+        assert_eq!(screen.get(60, 0), 0);
+        assert_eq!(screen.get(0, 0), 0);
+    }
+
+    #[test]
+    fn lores_sprites_wrap_around_right_and_bottom_edges() {
+        let cpu = draw_across_corner(&LORES_CORNER_ROM, false);
+        let screen = cpu.get_framebuffer();
+        assert_ne!(screen.get(63, 31), 0);
+        assert_ne!(screen.get(3, 30), 0);
+        assert_ne!(screen.get(60, 1), 0);
+        assert_ne!(screen.get(3, 1), 0);
+        assert_eq!(screen.get(4, 1), 0);
+        assert_eq!(screen.get(3, 2), 0);
+    }
+
+    #[test]
+    fn hires_sprites_clip_at_right_and_bottom_edges() {
+        let cpu = draw_across_corner(&hires_corner_rom(), true);
+        let screen = cpu.get_framebuffer();
+        assert_ne!(screen.get(120, 60), 0);
+        assert_ne!(screen.get(127, 63), 0);
+        assert_eq!(screen.get(0, 60), 0);
+        assert_eq!(screen.get(120, 0), 0);
+        assert_eq!(screen.get(0, 0), 0);
+    }
+
+    #[test]
+    fn hires_sprites_wrap_around_right_and_bottom_edges() {
+        let cpu = draw_across_corner(&hires_corner_rom(), false);
+        let screen = cpu.get_framebuffer();
+        assert_ne!(screen.get(127, 63), 0);
+        assert_ne!(screen.get(7, 60), 0);
+        assert_ne!(screen.get(120, 11), 0);
+        assert_ne!(screen.get(7, 11), 0);
+        assert_eq!(screen.get(8, 11), 0);
+        assert_eq!(screen.get(7, 12), 0);
+    }
+
+    #[test]
+    fn superchip_11_scrolls() {
+        // 00FF A000 D001 00FB: in HiRes, draw the top row of the 0 glyph at
+        // (0, 0) and scroll it 4 pixels right
+        let rom = [0x00, 0xFF, 0xA0, 0x00, 0xD0, 0x01, 0x00, 0xFB];
+        let mut cpu = cpu_with_rom(Chip8Variant::SuperChip11, &rom);
+        for _ in 0..4 {
+            cpu.tick();
+        }
+        let screen = cpu.get_framebuffer();
+        assert_eq!(screen.get(3, 0), 0);
+        assert_ne!(screen.get(4, 0), 0);
+        assert_ne!(screen.get(7, 0), 0);
+        assert_eq!(screen.get(8, 0), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid opcode")]
+    fn superchip_10_has_no_scrolling() {
+        let mut cpu = cpu_with_rom(Chip8Variant::SuperChip10, &[0x00, 0xFB]);
+        cpu.tick();
+    }
+
+    #[test]
+    fn get_rgba_colors_mono_pixels() {
+        // A000 D001: the top row of the 0 glyph at (0, 0)
+        let rom = [0xA0, 0x00, 0xD0, 0x01];
+        let mut cpu = cpu_with_rom(Chip8Variant::SuperChipModern, &rom);
+        cpu.tick();
+        cpu.tick();
+        let rgba = cpu.get_rgba(&[0xFF000000, 0xFF00FF00]);
+        assert_eq!(rgba.len(), 64 * 32 * 4);
+        assert_eq!(rgba[..4], [0, 0xFF, 0, 0xFF]);
+        assert_eq!(rgba[4 * 4..5 * 4], [0, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn get_rgba_uses_the_color_board_on_chip8x() {
+        // Same draw at 0x300, where CHIP-8X starts
+        let rom = [0xA0, 0x00, 0xD0, 0x01];
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8X, &rom);
+        for _ in 0..3 {
+            cpu.tick_timers();
+            cpu.tick();
+        }
+        let rgba = cpu.get_rgba(&MONO_PALETTE);
+        // Red foreground on a blue background
+        assert_eq!(rgba[..4], [0xFF, 0, 0, 0xFF]);
+        assert_eq!(rgba[4 * 4..5 * 4], [0, 0, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn breakpoint_on_entry_stops_again_after_reset() {
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &[0x60, 0x05]);
+        cpu.debugger_mut().add_breakpoint(0x200);
+        cpu.tick();
+        cpu.take_break();
+        cpu.tick();
+
+        cpu.reset();
+        cpu.load(&[0x60, 0x05]).unwrap();
+        cpu.tick();
+        assert_eq!(cpu.take_break(), Some(BreakReason::Breakpoint(0x200)));
+    }
+
+    #[test]
+    fn set_registers_on_megachip_keeps_full_addresses() {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::MegaChip);
+        let mut regs = cpu.get_registers();
+        regs.pc = 0xFFFE;
+        regs.i_reg = 0xABCDEF;
+        cpu.set_registers(regs);
+
+        let regs = cpu.get_registers();
+        assert_eq!(regs.pc, 0xFFFE);
+        assert_eq!(regs.i_reg, 0xABCDEF);
+    }
+
+    #[test]
+    fn megachip_reads_wrap_past_the_end_of_memory() {
+        // 0011 0302 0402 01FF FFFF D010 F165: 2x2 sprite and two registers
+        // from I = 0xFFFFFF, the last byte of memory
+        let rom = [
+            0x00, 0x11, 0x03, 0x02, 0x04, 0x02, 0x01, 0xFF, 0xFF, 0xFF, 0xD0, 0x10, 0xF1, 0x65,
+        ];
+        let mut cpu = cpu_with_rom(Chip8Variant::MegaChip, &rom);
+        for _ in 0..6 {
+            cpu.tick();
+        }
+        let regs = cpu.get_registers();
+        assert_eq!(regs.pc, 0x20E);
+        // V1 comes from address 0, the top row of the 0 glyph
+        assert_eq!(regs.v_reg[..2], [0x00, 0xF0]);
+    }
+
+    #[test]
+    fn pc_wraps_around_the_address_space() {
+        // 6005 at the last opcode of MegaChip's 64 KB
+        let mut cpu = cpu_with_rom(Chip8Variant::MegaChip, &[0x60, 0x05]);
+        cpu.poke(0xFFFE, 0x60);
+        cpu.poke(0xFFFF, 0x05);
+        let mut regs = cpu.get_registers();
+        regs.pc = 0xFFFE;
+        cpu.set_registers(regs);
+        cpu.tick();
+        assert_eq!(cpu.get_registers().pc, 0);
+
+        // 3000: skip the next instruction from 0xFFC, as V0 is 0
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &[0x60, 0x05]);
+        cpu.poke(0xFFC, 0x30);
+        let mut regs = cpu.get_registers();
+        regs.pc = 0xFFC;
+        cpu.set_registers(regs);
+        cpu.tick();
+        assert_eq!(cpu.get_registers().pc, 0);
+    }
+
+    #[test]
+    fn jump_with_offset_wraps_around_the_address_space() {
+        // 60FF BFFF: jump to 0xFFF + 0xFF
+        let mut cpu = cpu_with_rom(Chip8Variant::Chip8, &[0x60, 0xFF, 0xBF, 0xFF]);
+        cpu.tick();
+        cpu.tick();
+        assert_eq!(cpu.get_registers().pc, 0x0FE);
+    }
+
+    #[test]
+    fn set_registers_wraps_addresses_to_the_variant() {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        let mut regs = cpu.get_registers();
+        regs.pc = 0x1202;
+        regs.i_reg = 0x1300;
+        cpu.set_registers(regs);
+
+        let regs = cpu.get_registers();
+        assert_eq!(regs.pc, 0x202);
+        assert_eq!(regs.i_reg, 0x300);
+    }
+
+    #[test]
+    fn set_registers_keeps_pc_on_a_whole_opcode() {
+        let mut cpu = Cpu::new(AudioManager::silent(), Chip8Variant::Chip8);
+        let mut regs = cpu.get_registers();
+        regs.pc = 0xFFF;
+        cpu.set_registers(regs);
+        assert_eq!(cpu.get_registers().pc, 0xFFE);
+        assert_eq!(cpu.peek_opcode(), 0x0000);
+    }
+}
@@ -0,0 +1,319 @@
+// MegaChip display: a 256x192 ARGB screen drawn with palette sprites, and
+// the sampled sound format played by 060N.
+
+use crate::framebuffer::{Framebuffer, PixelFormat};
+
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
+
+// Drawn for the set bits of font sprites, which have no palette indices
+const MONO_COLOR: u32 = 0xFFFFFFFF;
+// Header of a sound: 16-bit sample rate, 24-bit length and a reserved byte
+const SOUND_HEADER: usize = 6;
+
+// Enumerable containing how 080N blends sprites onto the screen
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Normal,
+    // Sprite drawn at 25% and 50% opacity
+    Alpha25,
+    Alpha50,
+    Add,
+    Multiply,
+}
+
+impl BlendMode {
+    // Blend mode numbered n by 080N
+    pub fn from_index(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Alpha25),
+            2 => Some(BlendMode::Alpha50),
+            3 => Some(BlendMode::Add),
+            4 => Some(BlendMode::Multiply),
+            _ => None,
+        }
+    }
+
+    // Blend the ARGB src pixel onto dst, one channel at a time
+    fn blend(self, src: u32, dst: u32) -> u32 {
+        let channel = |shift: u32| {
+            let s = (src >> shift) & 0xFF;
+            let d = (dst >> shift) & 0xFF;
+            let value = match self {
+                BlendMode::Normal => s,
+                BlendMode::Alpha25 => (s + 3 * d) / 4,
+                BlendMode::Alpha50 => (s + d) / 2,
+                BlendMode::Add => (s + d).min(0xFF),
+                BlendMode::Multiply => s * d / 0xFF,
+            };
+            value << shift
+        };
+        0xFF000000 | channel(16) | channel(8) | channel(0)
+    }
+}
+
+// MegaChip state
+#[derive(Clone, Debug)]
+pub struct MegaChip {
+    // ARGB colors of palette indices 1 - 255, index 0 is transparent
+    palette: [u32; 256],
+    sprite_width: usize,
+    sprite_height: usize,
+    blend_mode: BlendMode,
+    // Palette index sprites collide with
+    collision_color: u8,
+    // Opacity of the whole screen, for fading
+    screen_alpha: u8,
+    // Frame being drawn, shown by the next 00E0
+    back: Framebuffer,
+    // Palette index last drawn at each pixel of the back buffer
+    indices: Framebuffer,
+    // Frame on display
+    front: Framebuffer,
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        Self {
+            palette: [0; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            blend_mode: BlendMode::Normal,
+            collision_color: 0,
+            screen_alpha: 0xFF,
+            back: Framebuffer::new(PixelFormat::Argb, MEGA_WIDTH, MEGA_HEIGHT),
+            indices: Framebuffer::new(PixelFormat::Indexed8, MEGA_WIDTH, MEGA_HEIGHT),
+            front: Framebuffer::new(PixelFormat::Argb, MEGA_WIDTH, MEGA_HEIGHT),
+        }
+    }
+
+    // 02NN - Set palette indices 1 - NN from colors, 4 bytes of ARGB each
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (idx, argb) in colors.chunks_exact(4).enumerate().take(255) {
+            self.palette[idx + 1] = u32::from_be_bytes([argb[0], argb[1], argb[2], argb[3]]);
+        }
+    }
+
+    // 03NN - Set the sprite width, 0 meaning 256
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = if width == 0 { 256 } else { width as usize };
+    }
+
+    // 04NN - Set the sprite height, 0 meaning 256
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = if height == 0 { 256 } else { height as usize };
+    }
+
+    // 05NN - Set the screen alpha
+    pub fn set_screen_alpha(&mut self, alpha: u8) {
+        self.screen_alpha = alpha;
+    }
+
+    // 080N - Set the blend mode
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    // 09NN - Set the collision color
+    pub fn set_collision_color(&mut self, color: u8) {
+        self.collision_color = color;
+    }
+
+    // Return the width and height of the sprites DXYN draws
+    pub fn get_sprite_size(&self) -> (usize, usize) {
+        (self.sprite_width, self.sprite_height)
+    }
+
+    // Return the frame on display
+    pub fn get_frame(&self) -> &Framebuffer {
+        &self.front
+    }
+
+    // Return the opacity of the whole screen
+    pub fn get_screen_alpha(&self) -> u8 {
+        self.screen_alpha
+    }
+
+    // 00E0 - Show the frame drawn so far and start a blank one
+    pub fn present(&mut self) {
+        std::mem::swap(&mut self.front, &mut self.back);
+        self.back.clear();
+        self.indices.clear();
+    }
+
+    // Scroll the frame being drawn by (dx, dy) pixels, blanking what
+    // scrolls in
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        self.back.scroll(dx, dy);
+        self.indices.scroll(dx, dy);
+    }
+
+    // DXYN - Draw a sprite of palette indices, width by height, clipped at
+    // the screen edges. Mono sprites are font data with one byte per pixel
+    // that is drawn white when not 0. Returns whether it collided
+    pub fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        pixels: &[u8],
+        mono: bool,
+    ) -> bool {
+        let mut collided = false;
+        for (row, row_pixels) in pixels.chunks(width.max(1)).enumerate() {
+            for (col, &index) in row_pixels.iter().enumerate() {
+                let (px, py) = (x + col, y + row);
+                if index == 0 || px >= MEGA_WIDTH || py >= MEGA_HEIGHT {
+                    continue;
+                }
+
+                let under = self.indices.get(px, py);
+                collided |= under != 0 && under == self.collision_color as u32;
+                let color = if mono {
+                    MONO_COLOR
+                } else {
+                    self.palette[index as usize]
+                };
+                let blended = self.blend_mode.blend(color, self.back.get(px, py));
+                self.back.set(px, py, blended);
+                self.indices.set(px, py, index as u32);
+            }
+        }
+        collided
+    }
+}
+
+// Turn a sound in MegaChip format into a WAV file of 8-bit unsigned mono
+// samples, None if the header is cut off
+pub fn sound_to_wav(sound: &[u8]) -> Option<Vec<u8>> {
+    let header = sound.get(..SOUND_HEADER)?;
+    let rate = u16::from_be_bytes([header[0], header[1]]) as u32;
+    let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+    let samples = &sound[SOUND_HEADER..(SOUND_HEADER + length).min(sound.len())];
+
+    let mut wav = Vec::with_capacity(44 + samples.len());
+    wav.extend(b"RIFF");
+    wav.extend((36 + samples.len() as u32).to_le_bytes());
+    wav.extend(b"WAVEfmt ");
+    wav.extend(16u32.to_le_bytes());
+    // PCM, 1 channel
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(rate.to_le_bytes());
+    // Byte rate, block size and bits per sample
+    wav.extend(rate.to_le_bytes());
+    wav.extend(1u16.to_le_bytes());
+    wav.extend(8u16.to_le_bytes());
+    wav.extend(b"data");
+    wav.extend((samples.len() as u32).to_le_bytes());
+    wav.extend(samples);
+    Some(wav)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u32 = 0xFFFF0000;
+    const GREEN: u32 = 0xFF00FF00;
+
+    // MegaChip with palette index 1 red and 2 green
+    fn mega_with_palette() -> MegaChip {
+        let mut mega = MegaChip::new();
+        mega.load_palette(&[0xFF, 0xFF, 0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00]);
+        mega
+    }
+
+    #[test]
+    fn load_palette_fills_from_index_1() {
+        let mega = mega_with_palette();
+        assert_eq!(mega.palette[..4], [0, RED, GREEN, 0]);
+    }
+
+    #[test]
+    fn blend_modes_mix_each_channel() {
+        let (src, dst) = (0xFF804020, 0xFF402080);
+        for (n, mode, blended) in [
+            (0, BlendMode::Normal, 0xFF804020),
+            (1, BlendMode::Alpha25, 0xFF502868),
+            (2, BlendMode::Alpha50, 0xFF603050),
+            (3, BlendMode::Add, 0xFFC060A0),
+            (4, BlendMode::Multiply, 0xFF200810),
+        ] {
+            assert_eq!(BlendMode::from_index(n), Some(mode));
+            assert_eq!(mode.blend(src, dst), blended, "{:?}", mode);
+        }
+        assert_eq!(BlendMode::from_index(5), None);
+        // Add saturates each channel
+        assert_eq!(BlendMode::Add.blend(0xFFF0F0F0, 0xFF202020), 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn draw_sprite_skips_transparent_pixels_and_reports_collisions() {
+        let mut mega = mega_with_palette();
+        mega.set_collision_color(2);
+        assert!(!mega.draw_sprite(10, 10, 2, &[2, 2, 2, 2], false));
+        // Index 0 leaves what's under it
+        assert!(mega.draw_sprite(10, 10, 2, &[1, 0, 0, 1], false));
+
+        // Nothing shows until the frame is presented
+        assert_eq!(mega.get_frame().get(10, 10), 0);
+        mega.present();
+        let frame = mega.get_frame();
+        assert_eq!(frame.get(10, 10), RED);
+        assert_eq!(frame.get(11, 10), GREEN);
+        assert_eq!(frame.get(10, 11), GREEN);
+        assert_eq!(frame.get(11, 11), RED);
+        assert_eq!(frame.get(12, 10), 0);
+    }
+
+    #[test]
+    fn draw_sprite_clips_at_the_screen_edges() {
+        let mut mega = mega_with_palette();
+        mega.draw_sprite(MEGA_WIDTH - 1, MEGA_HEIGHT - 1, 2, &[1, 1, 1, 1], false);
+        mega.present();
+        assert_eq!(mega.get_frame().get(MEGA_WIDTH - 1, MEGA_HEIGHT - 1), RED);
+        assert_eq!(mega.get_frame().get(0, 0), 0);
+    }
+
+    #[test]
+    fn mono_sprites_draw_white() {
+        let mut mega = mega_with_palette();
+        mega.draw_sprite(0, 0, 2, &[1, 0], true);
+        mega.present();
+        assert_eq!(mega.get_frame().get(0, 0), MONO_COLOR);
+        assert_eq!(mega.get_frame().get(1, 0), 0);
+    }
+
+    #[test]
+    fn sound_to_wav_writes_header_and_samples() {
+        // 8000 Hz, 3 samples, then a byte past the sound
+        let sound = [0x1F, 0x40, 0x00, 0x00, 0x03, 0x00, 0x10, 0x80, 0xF0, 0x55];
+        let wav = sound_to_wav(&sound).unwrap();
+        assert_eq!(wav.len(), 44 + 3);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(wav[4..8], 39u32.to_le_bytes());
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(wav[24..28], 8000u32.to_le_bytes());
+        assert_eq!(wav[34..36], 8u16.to_le_bytes());
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(wav[40..44], 3u32.to_le_bytes());
+        assert_eq!(wav[44..], [0x10, 0x80, 0xF0]);
+    }
+
+    #[test]
+    fn sound_to_wav_cuts_sounds_at_the_end_of_memory() {
+        assert_eq!(sound_to_wav(&[0x1F, 0x40, 0x00]), None);
+
+        let wav = sound_to_wav(&[0x1F, 0x40, 0x00, 0x01, 0x00, 0x00, 0x10]).unwrap();
+        assert_eq!(wav[40..44], 1u32.to_le_bytes());
+        assert_eq!(wav.len(), 45);
+    }
+}
@@ -0,0 +1,164 @@
+use crate::config::Chip8Variant;
+use crate::disasm::disassemble_with_symbols;
+use crate::symbols::Symbols;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// Number of addresses listed in the hot spot section of the report
+const REPORT_HOT_ADDRESSES: usize = 20;
+
+// Execution counts for one routine (a 2NNN call target)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RoutineStats {
+    // Number of times the routine was called
+    pub calls: u64,
+    // Instructions executed in the routine itself
+    pub self_count: u64,
+    // Instructions executed in the routine and everything it called
+    pub total_count: u64,
+}
+
+// Counts how often each address executes, grouped by routine.
+// Instructions outside of any call are attributed to the top level (None).
+pub struct Profiler {
+    counts: Vec<u64>,
+    routines: BTreeMap<Option<u16>, RoutineStats>,
+    // Entry addresses of the routines currently being executed
+    call_stack: Vec<u16>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            counts: vec![0; ram_size],
+            routines: BTreeMap::new(),
+            call_stack: Vec::new(),
+            total: 0,
+        }
+    }
+
+    // Times the instruction at each address executed
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn max_count(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn routines(&self) -> &BTreeMap<Option<u16>, RoutineStats> {
+        &self.routines
+    }
+
+    // Total instructions executed while profiling
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    // Record an executed instruction
+    pub(crate) fn record(&mut self, addr: u16, op: u16) {
+        if let Some(count) = self.counts.get_mut(addr as usize) {
+            *count += 1;
+        }
+        self.total += 1;
+
+        let current = self.call_stack.last().copied();
+        self.routines.entry(current).or_default().self_count += 1;
+
+        // Count towards every routine on the stack once, even if recursive
+        self.routines.entry(None).or_default().total_count += 1;
+        for (depth, entry) in self.call_stack.iter().enumerate() {
+            if !self.call_stack[..depth].contains(entry) {
+                self.routines.entry(Some(*entry)).or_default().total_count += 1;
+            }
+        }
+
+        match op {
+            // 2NNN - the callee starts counting from the next instruction
+            0x2000..=0x2FFF => {
+                let target = op & 0x0FFF;
+                self.routines.entry(Some(target)).or_default().calls += 1;
+                self.call_stack.push(target);
+            }
+            // 00EE
+            0x00EE => {
+                self.call_stack.pop();
+            }
+            _ => {}
+        }
+    }
+
+    // Plain text report of the hottest routines and addresses
+    pub fn report(&self, ram: &[u8], variant: Chip8Variant, symbols: Option<&Symbols>) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| count as f64 * 100.0 / self.total.max(1) as f64;
+
+        let _ = writeln!(report, "Instructions executed: {}", self.total);
+        let _ = writeln!(report);
+        let _ = writeln!(
+            report,
+            "{:<10} {:>8} {:>12} {:>7} {:>12} {:>7}",
+            "Routine", "Calls", "Self", "Self%", "Total", "Total%"
+        );
+
+        let mut routines: Vec<_> = self.routines.iter().collect();
+        routines.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.self_count));
+        for (entry, stats) in routines {
+            let name = match entry {
+                Some(addr) => match symbols.and_then(|s| s.label(*addr)) {
+                    Some(label) => label.to_string(),
+                    None => format!("{:#05X}", addr),
+                },
+                None => "<top>".to_string(),
+            };
+            let _ = writeln!(
+                report,
+                "{:<10} {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                name,
+                stats.calls,
+                stats.self_count,
+                percent(stats.self_count),
+                stats.total_count,
+                percent(stats.total_count)
+            );
+        }
+
+        let _ = writeln!(report);
+        let _ = writeln!(
+            report,
+            "{:<10} {:>12} {:>7}  Instruction",
+            "Address", "Count", "%"
+        );
+
+        let mut hot: Vec<_> = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .collect();
+        hot.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (addr, count) in hot.into_iter().take(REPORT_HOT_ADDRESSES) {
+            let op = match (ram.get(addr), ram.get(addr + 1)) {
+                (Some(high), Some(low)) => {
+                    disassemble_with_symbols((*high as u16) << 8 | *low as u16, variant, symbols)
+                }
+                _ => String::new(),
+            };
+            let location = match symbols.and_then(|s| s.describe(addr as u16)) {
+                Some(name) => format!("{:#05X} <{}>", addr, name),
+                None => format!("{:#05X}", addr),
+            };
+            let _ = writeln!(
+                report,
+                "{:<10} {:>12} {:>6.2}%  {}",
+                location,
+                count,
+                percent(*count),
+                op
+            );
+        }
+
+        report
+    }
+}
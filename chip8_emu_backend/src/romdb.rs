@@ -0,0 +1,274 @@
+use crate::config::{Chip8Variant, Ips, Quirks};
+use crate::error::ParseError;
+use std::collections::HashMap;
+
+// Settings the ROM database has for one ROM. Anything left None keeps the
+// choice made on the setup screen or the variant's default.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RomSettings {
+    pub variant: Option<Chip8Variant>,
+    pub ips: Option<Ips>,
+    pub vf_reset: Option<bool>,
+    pub shifting: Option<bool>,
+    pub jumping: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub clipping: Option<bool>,
+}
+
+impl RomSettings {
+    // Override the quirks the database has values for
+    pub fn apply_quirks(&self, quirks: &mut Quirks) {
+        let overrides = [
+            (self.vf_reset, &mut quirks.vf_reset),
+            (self.shifting, &mut quirks.shifting),
+            (self.jumping, &mut quirks.jumping),
+            (self.display_wait, &mut quirks.display_wait),
+            (self.clipping, &mut quirks.clipping),
+        ];
+        for (value, quirk) in overrides {
+            if let Some(value) = value {
+                *quirk = value;
+            }
+        }
+    }
+}
+
+// Per-ROM settings keyed by the SHA-1 of the ROM, the key the CHIP-8
+// community database uses too. One ROM per line:
+//
+//   <40 hex digit SHA-1> variant=superchip11 ips=1000 clipping=0 # comment
+//
+// variant is a lowercase Chip8Variant name, ips is anything Ips::parse
+// takes, and the quirks vf_reset, shifting, jumping, display_wait and
+// clipping are 0 or 1. Anything after a '#' and blank lines are ignored.
+#[derive(Clone, Debug, Default)]
+pub struct RomDatabase {
+    entries: HashMap<[u8; 20], RomSettings>,
+}
+
+impl RomDatabase {
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut entries = HashMap::new();
+
+        for (idx, raw_line) in text.lines().enumerate() {
+            let line = idx + 1;
+            let content = raw_line.split('#').next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+
+            let err = |message: String| ParseError { line, message };
+            let mut tokens = content.split_whitespace();
+            let hash = tokens.next().unwrap_or("");
+            let hash = parse_sha1(hash).ok_or_else(|| err(format!("invalid SHA-1: {}", hash)))?;
+
+            let mut settings = RomSettings::default();
+            for token in tokens {
+                let Some((key, value)) = token.split_once('=') else {
+                    return Err(err(format!("expected key=value, got {}", token)));
+                };
+                let invalid = || err(format!("invalid value for {}: {}", key, value));
+                let flag = || match value {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _ => Err(invalid()),
+                };
+
+                match key {
+                    "variant" => {
+                        settings.variant = Some(Chip8Variant::from_name(value).ok_or_else(invalid)?)
+                    }
+                    "ips" => settings.ips = Some(Ips::parse(value).ok_or_else(invalid)?),
+                    "vf_reset" => settings.vf_reset = Some(flag()?),
+                    "shifting" => settings.shifting = Some(flag()?),
+                    "jumping" => settings.jumping = Some(flag()?),
+                    "display_wait" => settings.display_wait = Some(flag()?),
+                    "clipping" => settings.clipping = Some(flag()?),
+                    _ => return Err(err(format!("unknown setting: {}", key))),
+                }
+            }
+            entries.insert(hash, settings);
+        }
+
+        Ok(Self { entries })
+    }
+
+    // Add the entries of another database, replacing ours for the same ROM
+    pub fn extend(&mut self, other: RomDatabase) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomSettings> {
+        self.entries.get(&sha1(rom))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn parse_sha1(text: &str) -> Option<[u8; 20]> {
+    if text.len() != 40 || !text.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 20];
+    for (byte, idx) in hash.iter_mut().zip((0..40).step_by(2)) {
+        *byte = u8::from_str_radix(&text[idx..idx + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+// SHA-1 (FIPS 180-4), only used to identify ROMs
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // Pad with a 1 bit, zeroes and the length in bits to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (idx, word) in block.chunks_exact(4).enumerate() {
+            w[idx] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for idx in 16..80 {
+            w[idx] = (w[idx - 3] ^ w[idx - 8] ^ w[idx - 14] ^ w[idx - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (idx, word) in w.iter().enumerate() {
+            let (f, k) = match idx {
+                0..20 => ((b & c) | (!b & d), 0x5A827999),
+                20..40 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..60 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (state, value) in h.iter_mut().zip([a, b, c, d, e]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    let mut hash = [0; 20];
+    for (bytes, state) in hash.chunks_exact_mut(4).zip(h) {
+        bytes.copy_from_slice(&state.to_be_bytes());
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::MemoryIncrement;
+
+    fn hex(hash: [u8; 20]) -> String {
+        hash.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn sha1_matches_known_digests() {
+        assert_eq!(hex(sha1(b"")), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(
+            hex(sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            hex(sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn parse_reads_settings_and_skips_comments() {
+        let rom = [0x12, 0x00];
+        let text = format!(
+            "# ROM database\n\
+             \n\
+             {} variant=superchip11 ips=1000 clipping=0 # a game\n\
+             {} ips=vip\n",
+            hex(sha1(&rom)),
+            "0".repeat(40)
+        );
+        let database = RomDatabase::parse(&text).unwrap();
+        assert_eq!(database.len(), 2);
+
+        let settings = database.lookup(&rom).unwrap();
+        assert_eq!(settings.variant, Some(Chip8Variant::SuperChip11));
+        assert_eq!(settings.ips, Some(Ips::Limited(1000)));
+        assert_eq!(settings.clipping, Some(false));
+        assert_eq!(settings.jumping, None);
+        assert!(database.lookup(&[0x12, 0x02]).is_none());
+    }
+
+    #[test]
+    fn parse_reports_line_of_errors() {
+        let hash = "a".repeat(40);
+        for (text, message) in [
+            ("1234 ips=500".to_string(), "invalid SHA-1: 1234"),
+            (format!("{} ips=fast", hash), "invalid value for ips: fast"),
+            (
+                format!("{} clipping=yes", hash),
+                "invalid value for clipping: yes",
+            ),
+            (
+                format!("{} variant=chip9", hash),
+                "invalid value for variant: chip9",
+            ),
+            (format!("{} speed=500", hash), "unknown setting: speed"),
+            (format!("{} ips", hash), "expected key=value, got ips"),
+        ] {
+            let error = RomDatabase::parse(&format!("\n{}", text)).unwrap_err();
+            assert_eq!(error.line, 2);
+            assert_eq!(error.message, message);
+        }
+    }
+
+    #[test]
+    fn extend_replaces_entries_for_the_same_rom() {
+        let hash = hex(sha1(&[0x00, 0xE0]));
+        let mut database = RomDatabase::parse(&format!("{} ips=500", hash)).unwrap();
+        database.extend(RomDatabase::parse(&format!("{} ips=unlimited", hash)).unwrap());
+        assert_eq!(database.len(), 1);
+        assert_eq!(
+            database.lookup(&[0x00, 0xE0]).unwrap().ips,
+            Some(Ips::Unlimited)
+        );
+    }
+
+    #[test]
+    fn apply_quirks_only_overrides_set_quirks() {
+        let settings = RomSettings {
+            shifting: Some(false),
+            clipping: Some(false),
+            ..Default::default()
+        };
+        let mut quirks = Quirks::new_variant(Chip8Variant::SuperChip11);
+        settings.apply_quirks(&mut quirks);
+        assert!(!quirks.shifting);
+        assert!(!quirks.clipping);
+        assert!(quirks.jumping);
+        assert_eq!(quirks.memory, MemoryIncrement::Unchanged);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::coverage::SourceLine;
+use crate::error::ParseError;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+// Labels and source lines for a ROM, as written by an assembler.
+//
+// One entry per line, '#' and ';' start comments:
+//   draw_player = 0x2A4      label (also "draw_player: 0x2A4")
+//   0x2A4 draw_player        label
+//   0x2A4 game.8o:120        source line of the instruction at 0x2A4
+// Addresses are hex with an optional 0x or $ prefix.
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    labels: BTreeMap<u16, String>,
+    addrs: HashMap<String, u16>,
+    lines: BTreeMap<u16, SourceLine>,
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    let hex = text
+        .strip_prefix("0x")
+        .or_else(|| text.strip_prefix("0X"))
+        .or_else(|| text.strip_prefix('$'))
+        .unwrap_or(text);
+    u16::from_str_radix(hex, 16).ok()
+}
+
+// "file:line" with a numeric line
+fn parse_source_line(text: &str) -> Option<SourceLine> {
+    let (file, line) = text.rsplit_once(':')?;
+    let line = line.parse().ok()?;
+    (!file.is_empty()).then(|| (file.to_string(), line))
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut symbols = Self::new();
+
+        for (idx, raw_line) in text.lines().enumerate() {
+            let line = idx + 1;
+            let content = raw_line.split(['#', ';']).next().unwrap_or("").trim();
+            if content.is_empty() {
+                continue;
+            }
+            let err = |message: &str| ParseError {
+                line,
+                message: format!("{}: {}", message, content),
+            };
+
+            // addr name / addr file:line
+            let tokens: Vec<&str> = content.split_whitespace().collect();
+            if let [addr, value] = tokens[..]
+                && let Some(addr) = parse_addr(addr)
+            {
+                match parse_source_line(value) {
+                    Some(source) => {
+                        symbols.lines.insert(addr, source);
+                    }
+                    None => symbols.add_label(addr, value),
+                }
+                continue;
+            }
+
+            // name = addr / name: addr
+            let Some((name, addr)) = content.split_once(['=', ':']) else {
+                return Err(err("expected a label and an address"));
+            };
+            let addr = parse_addr(addr.trim()).ok_or_else(|| err("invalid address"))?;
+            let name = name.trim();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return Err(err("invalid label"));
+            }
+            symbols.add_label(addr, name);
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn add_label(&mut self, addr: u16, name: &str) {
+        self.labels.insert(addr, name.to_string());
+        self.addrs.insert(name.to_string(), addr);
+    }
+
+    pub fn labels(&self) -> &BTreeMap<u16, String> {
+        &self.labels
+    }
+
+    // Label defined exactly at addr
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn addr_of(&self, name: &str) -> Option<u16> {
+        self.addrs.get(name).copied()
+    }
+
+    // Nearest label at or before addr, as "label" or "label+offset"
+    pub fn describe(&self, addr: u16) -> Option<String> {
+        let (base, name) = self.labels.range(..=addr).next_back()?;
+        Some(match addr - base {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset),
+        })
+    }
+
+    pub fn has_source_lines(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    pub fn source_line(&self, addr: u16) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+
+    // Address of the first instruction generated from file:line. file may be
+    // a longer path (e.g. absolute) than the symbol file has, but must end
+    // in all of its components
+    pub fn addr_of_line(&self, file: &str, line: u32) -> Option<u16> {
+        self.lines
+            .iter()
+            .find(|(_, (f, l))| *l == line && Path::new(file).ends_with(f))
+            .map(|(addr, _)| *addr)
+    }
+
+    // Resolve a label name or hex address
+    pub fn resolve(&self, text: &str) -> Option<u16> {
+        self.addr_of(text).or_else(|| parse_addr(text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_every_entry_form() {
+        let symbols = Symbols::parse(
+            "# labels\n\
+             \n\
+             main = 0x200\n\
+             draw_player: $2A4 ; the player sprite\n\
+             0x2B0 game_over\n\
+             0x200 game.8o:12\n\
+             2a4 src/game.8o:120\n",
+        )
+        .unwrap();
+
+        assert_eq!(symbols.addr_of("main"), Some(0x200));
+        assert_eq!(symbols.addr_of("draw_player"), Some(0x2A4));
+        assert_eq!(symbols.label(0x2B0), Some("game_over"));
+        assert_eq!(symbols.labels().len(), 3);
+        assert_eq!(
+            symbols.source_line(0x200),
+            Some(&("game.8o".to_string(), 12))
+        );
+        assert_eq!(
+            symbols.source_line(0x2A4),
+            Some(&("src/game.8o".to_string(), 120))
+        );
+        assert!(symbols.has_source_lines());
+    }
+
+    #[test]
+    fn parse_reports_line_of_errors() {
+        let no_addr = Symbols::parse("main = 0x200\n\nplayer\n").unwrap_err();
+        assert_eq!(no_addr.line, 3);
+
+        let bad_addr = Symbols::parse("main = 0xZZZ").unwrap_err();
+        assert_eq!(bad_addr.line, 1);
+        assert!(bad_addr.message.starts_with("invalid address"));
+
+        let bad_label = Symbols::parse("two words = 0x200").unwrap_err();
+        assert!(bad_label.message.starts_with("invalid label"));
+    }
+
+    #[test]
+    fn describe_uses_nearest_label_with_offset() {
+        let symbols = Symbols::parse("main = 0x200\nloop = 0x210\n").unwrap();
+        assert_eq!(symbols.describe(0x200).as_deref(), Some("main"));
+        assert_eq!(symbols.describe(0x20A).as_deref(), Some("main+10"));
+        assert_eq!(symbols.describe(0x214).as_deref(), Some("loop+4"));
+        assert_eq!(symbols.describe(0x1FE), None);
+    }
+
+    #[test]
+    fn addr_of_line_matches_whole_path_components() {
+        let symbols = Symbols::parse("0x200 game.8o:3\n0x210 src/lib.8o:3\n").unwrap();
+        assert_eq!(symbols.addr_of_line("game.8o", 3), Some(0x200));
+        assert_eq!(symbols.addr_of_line("/home/me/game.8o", 3), Some(0x200));
+        assert_eq!(symbols.addr_of_line("/home/me/othergame.8o", 3), None);
+        assert_eq!(symbols.addr_of_line("/home/me/src/lib.8o", 3), Some(0x210));
+        assert_eq!(symbols.addr_of_line("/home/me/lib.8o", 3), None);
+        assert_eq!(symbols.addr_of_line("game.8o", 4), None);
+    }
+
+    #[test]
+    fn resolve_takes_labels_or_addresses() {
+        let symbols = Symbols::parse("main = 0x200").unwrap();
+        assert_eq!(symbols.resolve("main"), Some(0x200));
+        assert_eq!(symbols.resolve("0x2A4"), Some(0x2A4));
+        assert_eq!(symbols.resolve("$300"), Some(0x300));
+        assert_eq!(symbols.resolve("nowhere"), None);
+    }
+}
@@ -0,0 +1,78 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+// Listening socket serving one client at a time, shared by the GDB and DAP
+// servers. Only sending blocks, so it can be polled once per frame.
+pub(crate) struct Connection {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    // Bytes received but not yet handled
+    pub(crate) input: Vec<u8>,
+}
+
+impl Connection {
+    pub(crate) fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            client: None,
+            input: Vec::new(),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub(crate) fn is_connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Accept a client if one is waiting, returning whether it did
+    pub(crate) fn accept(&mut self) -> bool {
+        let Ok((stream, _)) = self.listener.accept() else {
+            return false;
+        };
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let _ = stream.set_nodelay(true);
+        self.client = Some(stream);
+        self.input.clear();
+        true
+    }
+
+    // Add everything the client sent to the input, disconnecting if it left
+    pub(crate) fn receive(&mut self) {
+        let mut buffer = [0; 4096];
+        while let Some(client) = &mut self.client {
+            match client.read(&mut buffer) {
+                Ok(0) => self.disconnect(),
+                Ok(len) => self.input.extend_from_slice(&buffer[..len]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(_) => self.disconnect(),
+            }
+        }
+    }
+
+    pub(crate) fn send(&mut self, bytes: &[u8]) {
+        let Some(client) = &mut self.client else {
+            return;
+        };
+        // Block while writing so long messages aren't cut short
+        let sent = client
+            .set_nonblocking(false)
+            .and_then(|_| client.write_all(bytes))
+            .and_then(|_| client.set_nonblocking(true));
+        if sent.is_err() {
+            self.disconnect();
+        }
+    }
+
+    pub(crate) fn disconnect(&mut self) {
+        self.client = None;
+        self.input.clear();
+    }
+}
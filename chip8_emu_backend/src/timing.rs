@@ -0,0 +1,126 @@
+// Instruction timing of the original COSMAC VIP interpreter, in 1802 machine
+// cycles (8 clock cycles of the VIP's 1.7609 MHz clock).
+//
+// Costs are approximations of the interpreter's routines rather than a
+// measurement of every path through them, but keep the relative costs that
+// matter to ROMs: cheap register ops, slow 8XYN/FX33/FX55/FX65, and sprites
+// that get slower with height and with every bit they are shifted by.
+
+// Machine cycles in one 60 Hz frame
+pub const VIP_FRAME_CYCLES: u64 = 3668;
+// Cycles of each frame taken by the display DMA (128 scanlines of 8 bytes)
+// and the interrupt routine that counts the timers down
+pub const VIP_INTERRUPT_CYCLES: u64 = 1024 + 46;
+// Cycles left each frame for the interpreter
+pub const VIP_AVAILABLE_CYCLES: u64 = VIP_FRAME_CYCLES - VIP_INTERRUPT_CYCLES;
+
+// Fetching and decoding an instruction, paid by every opcode
+const FETCH: u32 = 40;
+// Extra cost of 3XNN, 4XNN, 5XY0, 9XY0, EX9E and EXA1 when they skip
+const SKIP: u32 = 4;
+
+// Machine cycles taken by op. vx is the value of VX before it ran and
+// skipped whether it skipped the next instruction.
+pub fn vip_cycles(op: u16, vx: u8, skipped: bool) -> u32 {
+    let x = ((op & 0x0F00) >> 8) as u32;
+    let n = (op & 0x000F) as u32;
+    let skip = if skipped { SKIP } else { 0 };
+
+    let execute = match op & 0xF000 {
+        0x0000 => match op {
+            // Clears all 256 bytes of display memory
+            0x00E0 => 3078,
+            // HIRES CHIP-8 clears both pages, 512 bytes
+            0x0230 => 2 * 3078,
+            0x00EE => 10,
+            // Machine code routines aren't emulated
+            _ => 0,
+        },
+        0x1000 => 12,
+        0x2000 => 26,
+        0x3000 | 0x4000 => 10 + skip,
+        0x5000 | 0x9000 => 14 + skip,
+        0x6000 => 6,
+        0x7000 => 10,
+        0x8000 => 44,
+        0xA000 => 12,
+        0xB000 => 22,
+        0xC000 => 36,
+        0xD000 => sprite_cycles(vx, n),
+        0xE000 => 14 + skip,
+        0xF000 => match op & 0x00FF {
+            0x07 | 0x15 | 0x18 => 10,
+            0x0A => 19,
+            0x1E | 0x29 => 16,
+            // BCD by repeated subtraction, one loop per unit of each digit
+            0x33 => {
+                let digits = vx / 100 + vx / 10 % 10 + vx % 10;
+                80 + 16 * digits as u32
+            }
+            0x55 | 0x65 => 14 + 14 * (x + 1),
+            _ => 0,
+        },
+        _ => 0,
+    };
+
+    FETCH + execute
+}
+
+// DXYN draws each row by shifting the sprite byte into place one bit at a
+// time, then XORing it into one byte of display memory, or two if unaligned
+fn sprite_cycles(x: u8, rows: u32) -> u32 {
+    let shift = (x % 8) as u32;
+    let per_row = if shift == 0 { 34 } else { 46 + 4 * shift };
+    68 + rows * per_row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_is_a_60th_of_the_vip_clock() {
+        // 1.7609 MHz, 8 clock cycles per machine cycle
+        assert_eq!(VIP_FRAME_CYCLES, 1_760_900 / 8 / 60);
+        assert_eq!(VIP_AVAILABLE_CYCLES, 2598);
+    }
+
+    #[test]
+    fn register_ops_are_cheap_and_memory_ops_slow() {
+        assert_eq!(vip_cycles(0x6A02, 0, false), 46);
+        assert_eq!(vip_cycles(0x7A01, 0, false), 50);
+        assert_eq!(vip_cycles(0x8AB4, 0, false), 84);
+        assert_eq!(vip_cycles(0x00E0, 0, false), 3118);
+        assert_eq!(vip_cycles(0x0230, 0, false), 40 + 2 * 3078);
+        // FX55 and FX65 copy X + 1 registers
+        assert_eq!(vip_cycles(0xF055, 0, false), 68);
+        assert_eq!(vip_cycles(0xFF65, 0, false), 278);
+    }
+
+    #[test]
+    fn skips_cost_extra_when_taken() {
+        assert_eq!(vip_cycles(0x3A02, 2, false), 50);
+        assert_eq!(vip_cycles(0x3A02, 2, true), 54);
+        assert_eq!(vip_cycles(0xEA9E, 0, true), 58);
+    }
+
+    #[test]
+    fn bcd_takes_longer_for_larger_digits() {
+        assert_eq!(vip_cycles(0xF033, 0, false), 120);
+        // 2 + 5 + 5 subtraction loops
+        assert_eq!(vip_cycles(0xF033, 255, false), 312);
+    }
+
+    #[test]
+    fn sprites_cost_per_row_and_shift() {
+        // Byte aligned, 34 cycles a row
+        assert_eq!(vip_cycles(0xD015, 0, false), 40 + 68 + 5 * 34);
+        assert_eq!(sprite_cycles(8, 1), 68 + 34);
+        assert_eq!(sprite_cycles(8, 15) - sprite_cycles(8, 14), 34);
+        // Shifted by 3 bits into two bytes
+        assert_eq!(vip_cycles(0xD015, 3, false), 40 + 68 + 5 * 58);
+        assert_eq!(sprite_cycles(7, 1), 68 + 74);
+        // Nothing to draw still sets up the sprite
+        assert_eq!(sprite_cycles(0, 0), 68);
+    }
+}
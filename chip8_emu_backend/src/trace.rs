@@ -0,0 +1,217 @@
+use crate::Registers;
+use crate::config::Chip8Variant;
+use crate::disasm::disassemble;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// One executed instruction along with the registers around it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub opcode: u16,
+    // Variant that ran the instruction, to disassemble it
+    pub variant: Chip8Variant,
+    // Registers before the instruction executed (before.pc is its address)
+    pub before: Registers,
+    // Registers after the instruction executed
+    pub after: Registers,
+}
+
+impl TraceEntry {
+    // Describe every register the instruction changed, PC excluded
+    pub fn changes(&self) -> Vec<String> {
+        let (b, a) = (&self.before, &self.after);
+        let mut changes = Vec::new();
+
+        for (idx, (old, new)) in b.v_reg.iter().zip(a.v_reg.iter()).enumerate() {
+            if old != new {
+                changes.push(format!("V{:X}:{:02X}->{:02X}", idx, old, new));
+            }
+        }
+        if b.i_reg != a.i_reg {
+            changes.push(format!("I:{:04X}->{:04X}", b.i_reg, a.i_reg));
+        }
+        if b.sp != a.sp {
+            changes.push(format!("SP:{:X}->{:X}", b.sp, a.sp));
+        }
+        if b.delay_t != a.delay_t {
+            changes.push(format!("DT:{:02X}->{:02X}", b.delay_t, a.delay_t));
+        }
+        if b.sound_t != a.sound_t {
+            changes.push(format!("ST:{:02X}->{:02X}", b.sound_t, a.sound_t));
+        }
+
+        changes
+    }
+}
+
+// Trace line format, one instruction per line:
+// PC=0200 OP=6A02 V=00000000000000000000000000000000 I=0000 SP=0 DT=00 ST=00 | LD VA, 0x02 | VA:00->02
+// The key=value fields hold the state before the instruction ran, so traces
+// from other emulators only need the fields before the first '|'
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = &self.before;
+        write!(f, "PC={:04X} OP={:04X} V=", b.pc, self.opcode)?;
+        for v in b.v_reg {
+            write!(f, "{:02X}", v)?;
+        }
+        write!(
+            f,
+            " I={:04X} SP={:X} DT={:02X} ST={:02X} | {} | {}",
+            b.i_reg,
+            b.sp,
+            b.delay_t,
+            b.sound_t,
+            disassemble(self.opcode, self.variant),
+            self.changes().join(" ")
+        )
+    }
+}
+
+// Records executed instructions into a ring buffer and optionally a file
+pub struct Tracer {
+    entries: VecDeque<TraceEntry>,
+    capacity: usize,
+    // Only record instructions with an address inside this inclusive range
+    range: Option<(u16, u16)>,
+    file: Option<BufWriter<File>>,
+    // First error hit while writing to the file, after which writing stops
+    file_error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            range: None,
+            file: None,
+            file_error: None,
+        }
+    }
+
+    // Only trace instructions with addresses within start..=end
+    pub fn with_range(mut self, start: u16, end: u16) -> Self {
+        self.range = Some((start, end));
+        self
+    }
+
+    // Also write every traced instruction to file
+    pub fn with_file(mut self, file: File) -> Self {
+        self.file = Some(BufWriter::new(file));
+        self
+    }
+
+    pub fn entries(&self) -> &VecDeque<TraceEntry> {
+        &self.entries
+    }
+
+    pub fn range(&self) -> Option<(u16, u16)> {
+        self.range
+    }
+
+    pub fn is_writing_file(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn file_error(&self) -> Option<&io::Error> {
+        self.file_error.as_ref()
+    }
+
+    // Write any buffered lines out to the trace file
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn wants(&self, pc: u16) -> bool {
+        self.range
+            .is_none_or(|(start, end)| start <= pc && pc <= end)
+    }
+
+    pub(crate) fn record(&mut self, entry: TraceEntry) {
+        if self.capacity > 0 {
+            if self.entries.len() == self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+
+        if let Some(file) = &mut self.file
+            && let Err(e) = writeln!(file, "{}", entry)
+        {
+            self.file = None;
+            self.file_error = Some(e);
+        }
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD VA, 0x02 at pc, leaving PC on the next instruction
+    fn entry(pc: u16) -> TraceEntry {
+        let before = Registers {
+            pc,
+            i_reg: 0x300,
+            sp: 1,
+            v_reg: [0; 16],
+            delay_t: 0x10,
+            sound_t: 0,
+        };
+        let mut after = before;
+        after.pc = pc + 2;
+        after.v_reg[0xA] = 0x02;
+        TraceEntry {
+            opcode: 0x6A02,
+            variant: Chip8Variant::Chip8,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn ring_buffer_drops_oldest_entries() {
+        let mut tracer = Tracer::new(3);
+        for pc in (0x200..0x20A).step_by(2) {
+            tracer.record(entry(pc));
+        }
+        let pcs: Vec<u16> = tracer.entries().iter().map(|e| e.before.pc).collect();
+        assert_eq!(pcs, [0x204, 0x206, 0x208]);
+
+        // Nothing is kept without a capacity
+        let mut tracer = Tracer::new(0);
+        tracer.record(entry(0x200));
+        assert!(tracer.entries().is_empty());
+    }
+
+    #[test]
+    fn formats_state_disassembly_and_changes() {
+        assert_eq!(
+            entry(0x200).to_string(),
+            "PC=0200 OP=6A02 V=00000000000000000000000000000000 I=0300 SP=1 DT=10 ST=00 \
+             | LD VA, 0x02 | VA:00->02"
+        );
+    }
+
+    #[test]
+    fn range_limits_traced_addresses() {
+        let tracer = Tracer::new(1).with_range(0x204, 0x208);
+        assert!(!tracer.wants(0x202));
+        assert!(tracer.wants(0x204));
+        assert!(tracer.wants(0x208));
+        assert!(!tracer.wants(0x20A));
+        assert!(Tracer::new(1).wants(0x000));
+    }
+}
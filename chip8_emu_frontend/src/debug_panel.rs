@@ -0,0 +1,744 @@
+use chip8_emu_backend::disasm::disassemble_with_symbols;
+use chip8_emu_backend::*;
+use macroquad::prelude::*;
+use rfd::FileDialog;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+// Panel drawn to the right of the game screen
+pub const PANEL_WIDTH: i32 = 460;
+pub const PANEL_MIN_HEIGHT: i32 = 800;
+
+const FONT_SIZE: u16 = 16;
+const LINE_HEIGHT: f32 = 18.0;
+const PADDING: f32 = 10.0;
+// Bytes shown per row of the hex view
+const ROW_BYTES: usize = 16;
+// Lines used by the register view above the hex view
+const HEADER_LINES: usize = 9;
+// Lines used by the call stack, access log and trace below the hex view
+const LOG_LINES: usize = 5;
+const FOOTER_LINES: usize = LOG_LINES * 3;
+// Instructions kept by the tracer for display
+const TRACE_SIZE: usize = 1024;
+
+const PANEL_BG: Color = Color::new(0.08, 0.08, 0.1, 1.0);
+const PC_HIGHLIGHT: Color = Color::new(0.2, 0.6, 0.2, 1.0);
+const I_HIGHLIGHT: Color = Color::new(0.2, 0.3, 0.7, 1.0);
+const BREAKPOINT_HIGHLIGHT: Color = Color::new(0.7, 0.15, 0.15, 1.0);
+const SELECTION_HIGHLIGHT: Color = Color::new(0.35, 0.35, 0.35, 1.0);
+
+pub struct DebugPanel {
+    pub visible: bool,
+    // First row of memory shown in the hex view
+    scroll_row: usize,
+    // Keep the row containing PC on screen while running
+    follow_pc: bool,
+    // Byte selected for editing
+    cursor: Option<u16>,
+    // Other end of a shift-click range selection
+    selection_end: Option<u16>,
+    // High nibble typed while editing the selected byte
+    pending_nibble: Option<u8>,
+    // Why execution last stopped
+    pub stop_reason: Option<String>,
+    // Command being typed, while the prompt is open
+    command: Option<String>,
+    // Result of the last command
+    message: Option<String>,
+}
+
+impl DebugPanel {
+    pub fn new() -> Self {
+        Self {
+            visible: false,
+            scroll_row: 0,
+            follow_pc: true,
+            cursor: None,
+            selection_end: None,
+            pending_nibble: None,
+            stop_reason: None,
+            command: None,
+            message: None,
+        }
+    }
+
+    // Handle scrolling, selection and editing input
+    pub fn update(&mut self, cpu: &mut Cpu, screen_px_width: f32, paused: bool) {
+        // Drain typed characters every frame so they don't pile up while running
+        let mut typed = Vec::new();
+        while let Some(c) = get_char_pressed() {
+            typed.push(c);
+        }
+
+        if !self.visible {
+            return;
+        }
+
+        // [Enter] opens the command prompt, see run_command
+        if let Some(mut command) = self.command.take() {
+            command.extend(typed.into_iter().filter(|c| !c.is_control()));
+            if is_key_pressed(KeyCode::Backspace) {
+                command.pop();
+            }
+            if is_key_pressed(KeyCode::Enter) {
+                self.message = Some(self.run_command(cpu, &command));
+            } else if !is_key_pressed(KeyCode::Escape) {
+                self.command = Some(command);
+            }
+            return;
+        }
+        if is_key_pressed(KeyCode::Enter) {
+            self.command = Some(String::new());
+            self.message = None;
+            return;
+        }
+
+        let ram_len = cpu.get_ram().len();
+        let total_rows = ram_len / ROW_BYTES;
+        let visible_rows = self.visible_rows();
+
+        // Scroll with the mouse wheel or PageUp/PageDown, Home to follow PC again
+        let (_, wheel_y) = mouse_wheel();
+        if wheel_y != 0.0 {
+            self.follow_pc = false;
+            if wheel_y > 0.0 {
+                self.scroll_row = self.scroll_row.saturating_sub(2);
+            } else {
+                self.scroll_row += 2;
+            }
+        }
+        if is_key_pressed(KeyCode::PageUp) {
+            self.follow_pc = false;
+            self.scroll_row = self.scroll_row.saturating_sub(visible_rows);
+        }
+        if is_key_pressed(KeyCode::PageDown) {
+            self.follow_pc = false;
+            self.scroll_row += visible_rows;
+        }
+        if is_key_pressed(KeyCode::Home) {
+            self.follow_pc = true;
+        }
+
+        if self.follow_pc {
+            let pc_row = cpu.get_registers().pc as usize / ROW_BYTES;
+            if pc_row < self.scroll_row || pc_row >= self.scroll_row + visible_rows {
+                self.scroll_row = pc_row.saturating_sub(visible_rows / 2);
+            }
+        }
+        self.scroll_row = self.scroll_row.min(total_rows.saturating_sub(visible_rows));
+
+        // Select a byte by clicking on it, or a range with shift-click
+        if is_mouse_button_pressed(MouseButton::Left) {
+            let (mx, my) = mouse_position();
+            let addr = self.byte_at(mx - screen_px_width, my, ram_len);
+            let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+            if shift && self.cursor.is_some() && addr.is_some() {
+                self.selection_end = addr;
+            } else {
+                self.cursor = addr;
+                self.selection_end = None;
+            }
+            self.pending_nibble = None;
+        }
+
+        // [F2] breakpoint, [F3] write watch, [F4] read/write watch, [F5] clear watches
+        let debugger = cpu.debugger_mut();
+        if let Some((start, end)) = self.selection() {
+            if is_key_pressed(KeyCode::F2) {
+                debugger.toggle_breakpoint(start);
+            }
+            if is_key_pressed(KeyCode::F3) {
+                debugger.remove_watchpoints(start, end);
+                debugger.add_watchpoint(Watchpoint {
+                    start,
+                    end,
+                    kind: WatchKind::Write,
+                });
+            }
+            if is_key_pressed(KeyCode::F4) {
+                debugger.remove_watchpoints(start, end);
+                debugger.add_watchpoint(Watchpoint {
+                    start,
+                    end,
+                    kind: WatchKind::ReadWrite,
+                });
+            }
+            if is_key_pressed(KeyCode::F5) {
+                debugger.remove_watchpoints(start, end);
+            }
+        }
+        // [F6] toggles the memory access log
+        if is_key_pressed(KeyCode::F6) {
+            let enabled = debugger.logging_accesses();
+            debugger.set_log_accesses(!enabled);
+        }
+        // [F9] toggles the profiler, [F10] saves its report
+        if is_key_pressed(KeyCode::F9) {
+            if cpu.profiler().is_some() {
+                cpu.set_profiler(None);
+            } else {
+                cpu.set_profiler(Some(Profiler::new(ram_len)));
+            }
+        }
+        if is_key_pressed(KeyCode::F10)
+            && let Some(profiler) = cpu.profiler()
+            && let Some(path) = FileDialog::new()
+                .add_filter("Profile", &["txt"])
+                .set_file_name("profile.txt")
+                .save_file()
+            && let Err(e) = fs::write(
+                &path,
+                profiler.report(cpu.get_ram(), cpu.get_variant(), cpu.debugger().symbols()),
+            )
+        {
+            crate::show_error(&format!("Unable to write {}: {}", path.display(), e));
+        }
+
+        // [F11] toggles coverage recording, [F12] saves an lcov report
+        if is_key_pressed(KeyCode::F11) {
+            if cpu.coverage().is_some() {
+                cpu.set_coverage(None);
+            } else {
+                cpu.set_coverage(Some(Coverage::new(ram_len)));
+            }
+        }
+        if is_key_pressed(KeyCode::F12)
+            && let Some(coverage) = cpu.coverage()
+            && let Some(path) = FileDialog::new()
+                .add_filter("lcov", &["info"])
+                .set_file_name("coverage.info")
+                .save_file()
+            && let Err(e) = save_coverage(cpu, coverage, &path)
+        {
+            crate::show_error(&format!("Unable to write coverage report: {}", e));
+        }
+
+        // [F7] traces into memory, [F8] traces into a file
+        if is_key_pressed(KeyCode::F7) {
+            if cpu.tracer().is_some() {
+                self.stop_trace(cpu);
+            } else {
+                cpu.set_tracer(Some(self.new_tracer()));
+            }
+        }
+        if is_key_pressed(KeyCode::F8) {
+            if cpu.tracer().is_some() {
+                self.stop_trace(cpu);
+            } else if let Some(path) = FileDialog::new()
+                .add_filter("Trace", &["log", "txt"])
+                .set_file_name("trace.log")
+                .save_file()
+            {
+                match File::create(&path) {
+                    Ok(file) => {
+                        cpu.set_tracer(Some(self.new_tracer().with_file(file)));
+                    }
+                    Err(e) => {
+                        crate::show_error(&format!("Unable to create {}: {}", path.display(), e))
+                    }
+                }
+            }
+        }
+
+        // Only allow poking memory while paused
+        if !paused {
+            self.pending_nibble = None;
+            return;
+        }
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+
+        let mut next = cursor as i32;
+        if is_key_pressed(KeyCode::Left) {
+            next -= 1;
+        }
+        if is_key_pressed(KeyCode::Right) {
+            next += 1;
+        }
+        if is_key_pressed(KeyCode::Up) {
+            next -= ROW_BYTES as i32;
+        }
+        if is_key_pressed(KeyCode::Down) {
+            next += ROW_BYTES as i32;
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            self.pending_nibble = None;
+        }
+        // Stay inside the variant's address space
+        let mut next = next.clamp(0, ram_len as i32 - 1) as usize;
+
+        for c in typed {
+            let Some(nibble) = c.to_digit(16) else {
+                continue;
+            };
+            match self.pending_nibble.take() {
+                None => self.pending_nibble = Some(nibble as u8),
+                Some(high) => {
+                    cpu.poke(next as u16, (high << 4) | nibble as u8);
+                    // The last byte keeps the cursor
+                    if next + 1 < ram_len {
+                        next += 1;
+                    }
+                }
+            }
+        }
+
+        if next != cursor as usize {
+            self.pending_nibble = None;
+            self.selection_end = None;
+            self.cursor = Some(next as u16);
+            self.ensure_visible(next / ROW_BYTES, total_rows);
+        }
+    }
+
+    pub fn draw(&self, cpu: &Cpu, x0: f32, paused: bool) {
+        draw_rectangle(x0, 0.0, PANEL_WIDTH as f32, screen_height(), PANEL_BG);
+
+        let regs = cpu.get_registers();
+        let ram = cpu.get_ram();
+        let x = x0 + PADDING;
+        let mut line = 0;
+
+        let (status, status_color) = if paused {
+            ("PAUSED  [P] resume  arrows/hex to edit", YELLOW)
+        } else {
+            ("RUNNING  [P] pause", GREEN)
+        };
+        self.text(status, x, line, status_color);
+        line += 1;
+        self.text(
+            &format!(
+                "PC {:04X}  I {:04X}  SP {:X}  DT {:02X}  ST {:02X}",
+                regs.pc, regs.i_reg, regs.sp, regs.delay_t, regs.sound_t
+            ),
+            x,
+            line,
+            WHITE,
+        );
+        line += 1;
+        for (offset, values) in regs.v_reg.chunks(8).enumerate() {
+            let text: Vec<String> = values
+                .iter()
+                .enumerate()
+                .map(|(i, v)| format!("V{:X} {:02X}", offset * 8 + i, v))
+                .collect();
+            self.text(&text.join(" "), x, line, WHITE);
+            line += 1;
+        }
+        let follow = if self.follow_pc { "on" } else { "off [Home]" };
+        self.text(
+            &format!("Follow PC: {}  [F2-F5] break/watch", follow),
+            x,
+            line,
+            GRAY,
+        );
+        line += 1;
+        let profile = match cpu.profiler() {
+            Some(profiler) => format!("on, {} instructions", profiler.total()),
+            None => "off".to_string(),
+        };
+        self.text(&format!("Profiler [F9/F10]: {}", profile), x, line, GRAY);
+        line += 1;
+        let covered = match cpu.coverage() {
+            Some(coverage) => {
+                let (start, end) = cpu.get_rom_bounds();
+                format!("on, {:.1}% of ROM", coverage.percent(start, end))
+            }
+            None => "off".to_string(),
+        };
+        self.text(&format!("Coverage [F11/F12]: {}", covered), x, line, GRAY);
+        line += 1;
+
+        let debugger = cpu.debugger();
+        let symbols = debugger.symbols();
+        self.text(
+            &format!(
+                "{}: {}",
+                debugger.describe(regs.pc),
+                disassemble_with_symbols(cpu.peek_opcode(), cpu.get_variant(), symbols)
+            ),
+            x,
+            line,
+            WHITE,
+        );
+        line += 1;
+        if let Some(command) = &self.command {
+            self.text(&format!("> {}_", command), x, line, YELLOW);
+        } else if let Some(message) = &self.message {
+            self.text(message, x, line, YELLOW);
+        } else if let Some(reason) = &self.stop_reason {
+            self.text(&format!("Stopped: {}", reason), x, line, RED);
+        } else {
+            self.text(
+                "[Enter] command: b/g <label|addr>, stack, port, sym",
+                x,
+                line,
+                GRAY,
+            );
+        }
+
+        let selection = self.selection();
+        let heat = cpu.profiler().map(|p| (p.counts(), p.max_count()));
+
+        // Hex view
+        let char_width = char_width();
+        for row in 0..self.visible_rows() {
+            let row_idx = self.scroll_row + row;
+            let base = row_idx * ROW_BYTES;
+            if base >= ram.len() {
+                break;
+            }
+            let y_line = HEADER_LINES + row;
+            self.text(&format!("{:04X}:", base), x, y_line, GRAY);
+
+            for col in 0..ROW_BYTES {
+                let addr = base + col;
+                let bx = x + char_width * (6 + col * 3) as f32;
+                let by = PADDING + LINE_HEIGHT * y_line as f32;
+
+                // Highlight the current opcode and the byte I points to
+                let pc = regs.pc as usize;
+                let selected = selection
+                    .is_some_and(|(start, end)| start as usize <= addr && addr <= end as usize);
+                let highlight = if addr == pc || addr == pc + 1 {
+                    Some(PC_HIGHLIGHT)
+                } else if debugger.breakpoints().contains(&(addr as u16)) {
+                    Some(BREAKPOINT_HIGHLIGHT)
+                } else if addr == regs.i_reg as usize {
+                    Some(I_HIGHLIGHT)
+                } else if selected {
+                    Some(SELECTION_HIGHLIGHT)
+                } else if let Some((counts, max)) = heat {
+                    // Both bytes of an executed instruction share its count
+                    let count = counts[addr].max(if addr > 0 { counts[addr - 1] } else { 0 });
+                    heat_color(count, max)
+                } else {
+                    None
+                };
+                if let Some(color) = highlight {
+                    draw_rectangle(bx, by, char_width * 2.0, LINE_HEIGHT, color);
+                }
+                // Underline watched bytes
+                if debugger
+                    .watchpoints()
+                    .iter()
+                    .any(|w| w.contains(addr as u16))
+                {
+                    draw_line(
+                        bx,
+                        by + LINE_HEIGHT - 1.0,
+                        bx + char_width * 2.0,
+                        by + LINE_HEIGHT - 1.0,
+                        2.0,
+                        RED,
+                    );
+                }
+
+                let (text, color) = match (self.cursor, self.pending_nibble) {
+                    (Some(c), Some(high)) if c as usize == addr => (format!("{:X}_", high), YELLOW),
+                    (Some(c), None) if c as usize == addr => (format!("{:02X}", ram[addr]), YELLOW),
+                    _ => (format!("{:02X}", ram[addr]), WHITE),
+                };
+                if self.cursor == Some(addr as u16) {
+                    draw_rectangle_lines(bx, by, char_width * 2.0, LINE_HEIGHT, 1.0, YELLOW);
+                }
+                self.text(&text, bx, y_line, color);
+            }
+        }
+
+        // Call stack, innermost call first
+        let line = HEADER_LINES + self.visible_rows();
+        let stack = cpu.get_call_stack();
+        self.text(
+            &format!(
+                "Call stack ({}/{}, {:?} on overflow)",
+                stack.len(),
+                STACK_SIZE,
+                cpu.stack_policy()
+            ),
+            x,
+            line,
+            GRAY,
+        );
+        for (depth, ret) in stack.iter().rev().take(LOG_LINES - 1).enumerate() {
+            // The 2NNN that pushed this return address sits just before it
+            let call_site = ret.wrapping_sub(2);
+            let high = ram.get(call_site as usize).copied().unwrap_or(0) as u16;
+            let low = ram.get(call_site as usize + 1).copied().unwrap_or(0) as u16;
+            let target = (high << 8 | low) & 0x0FFF;
+            let text = if depth == LOG_LINES - 2 && stack.len() > LOG_LINES - 1 {
+                format!("... {} more", stack.len() - depth)
+            } else {
+                format!(
+                    "#{} {} from {}",
+                    depth,
+                    debugger.describe(target),
+                    debugger.describe(call_site)
+                )
+            };
+            self.text(&text, x, line + 1 + depth, WHITE);
+        }
+
+        // Access log
+        let mut line = HEADER_LINES + self.visible_rows() + LOG_LINES;
+        if debugger.logging_accesses() {
+            self.text("Access log [F6]: on", x, line, GRAY);
+            let log = debugger.access_log();
+            for access in log.iter().skip(log.len().saturating_sub(LOG_LINES - 1)) {
+                line += 1;
+                self.text(&access.to_string(), x, line, WHITE);
+            }
+        } else {
+            self.text("Access log [F6]: off", x, line, GRAY);
+        }
+
+        // Execution trace
+        let line = HEADER_LINES + self.visible_rows() + LOG_LINES * 2;
+        match cpu.tracer() {
+            Some(tracer) => {
+                let target = if tracer.is_writing_file() {
+                    "file"
+                } else {
+                    "memory"
+                };
+                let range = match tracer.range() {
+                    Some((start, end)) => format!(" {:04X}-{:04X}", start, end),
+                    None => String::new(),
+                };
+                self.text(
+                    &format!("Trace [F7/F8]: {}{}", target, range),
+                    x,
+                    line,
+                    GRAY,
+                );
+                let entries = tracer.entries();
+                let skip = entries.len().saturating_sub(LOG_LINES - 1);
+                for (i, entry) in entries.iter().skip(skip).enumerate() {
+                    let text = format!(
+                        "{:04X}: {:04X} {:<16} {}",
+                        entry.before.pc,
+                        entry.opcode,
+                        disassemble_with_symbols(entry.opcode, entry.variant, symbols),
+                        entry.changes().join(" ")
+                    );
+                    self.text(&text, x, line + 1 + i, WHITE);
+                }
+            }
+            None => self.text("Trace [F7/F8]: off", x, line, GRAY),
+        }
+    }
+
+    // Trace the selected range if one is selected, otherwise everything
+    fn new_tracer(&self) -> Tracer {
+        let tracer = Tracer::new(TRACE_SIZE);
+        match (self.selection(), self.selection_end) {
+            (Some((start, end)), Some(_)) => tracer.with_range(start, end),
+            _ => tracer,
+        }
+    }
+
+    fn stop_trace(&self, cpu: &mut Cpu) {
+        if let Some(mut tracer) = cpu.set_tracer(None) {
+            let result = match tracer.file_error() {
+                Some(e) => Err(e.to_string()),
+                None => tracer.flush().map_err(|e| e.to_string()),
+            };
+            if let Err(e) = result {
+                crate::show_error(&format!("Unable to write trace: {}", e));
+            }
+        }
+    }
+
+    // True while the command prompt is taking keyboard input
+    pub fn is_typing(&self) -> bool {
+        self.command.is_some()
+    }
+
+    // Run a prompt command, returning a message describing the result
+    fn run_command(&mut self, cpu: &mut Cpu, command: &str) -> String {
+        let mut args = command.split_whitespace();
+        let ram_len = cpu.get_ram().len();
+        let debugger = cpu.debugger_mut();
+
+        match (args.next(), args.next()) {
+            // b <label|addr> - toggle breakpoint
+            (Some("b"), Some(target)) => match debugger.resolve(target) {
+                Some(addr) if addr as usize >= ram_len => {
+                    format!("Address out of range: {:04X}", addr)
+                }
+                Some(addr) => {
+                    debugger.toggle_breakpoint(addr);
+                    let state = if debugger.breakpoints().contains(&addr) {
+                        "set"
+                    } else {
+                        "cleared"
+                    };
+                    format!("Breakpoint {} at {}", state, debugger.describe(addr))
+                }
+                None => format!("Unknown label or address: {}", target),
+            },
+            // g <label|addr> - select address in the memory view
+            (Some("g"), Some(target)) => match debugger.resolve(target) {
+                Some(addr) if addr as usize >= ram_len => {
+                    format!("Address out of range: {:04X}", addr)
+                }
+                Some(addr) => {
+                    self.cursor = Some(addr);
+                    self.selection_end = None;
+                    self.follow_pc = false;
+                    self.scroll_row =
+                        (addr as usize / ROW_BYTES).saturating_sub(self.visible_rows() / 2);
+                    format!("Showing {}", cpu.debugger().describe(addr))
+                }
+                None => format!("Unknown label or address: {}", target),
+            },
+            // stack <error|break|wrap> - set the stack overflow policy
+            (Some("stack"), Some(policy)) => {
+                let policy = match policy {
+                    "error" => StackPolicy::Error,
+                    "break" => StackPolicy::Break,
+                    "wrap" => StackPolicy::Wrap,
+                    _ => return format!("Unknown stack policy: {}", policy),
+                };
+                cpu.set_stack_policy(policy);
+                format!("Stack policy: {:?}", policy)
+            }
+            // port [hex] - send a byte to the CHIP-8X I/O port, or show the
+            // last byte written to it
+            (Some("port"), Some(value)) => match u8::from_str_radix(value, 16) {
+                Ok(value) => {
+                    cpu.io_port_mut().send(value);
+                    format!("Sent {:02X} to the I/O port", value)
+                }
+                Err(_) => format!("Invalid byte: {}", value),
+            },
+            (Some("port"), None) => match cpu.io_port_mut().get_output() {
+                Some(value) => format!("I/O port output: {:02X}", value),
+                None => "Nothing written to the I/O port".to_string(),
+            },
+            // sym - load a symbol file
+            (Some("sym"), None) => {
+                let Some(path) = FileDialog::new()
+                    .add_filter("Symbols", &["sym", "txt"])
+                    .add_filter("All Files", &["*"])
+                    .pick_file()
+                else {
+                    return "No symbol file selected".to_string();
+                };
+                match crate::read_symbols(&path) {
+                    Ok(symbols) => {
+                        let count = symbols.labels().len();
+                        debugger.set_symbols(Some(symbols));
+                        format!("Loaded {} labels", count)
+                    }
+                    Err(e) => e,
+                }
+            }
+            _ => format!("Unknown command: {}", command),
+        }
+    }
+
+    // Selected address range, inclusive
+    fn selection(&self) -> Option<(u16, u16)> {
+        let cursor = self.cursor?;
+        let end = self.selection_end.unwrap_or(cursor);
+        Some((cursor.min(end), cursor.max(end)))
+    }
+
+    fn text(&self, text: &str, x: f32, line: usize, color: Color) {
+        let y = PADDING + LINE_HEIGHT * (line + 1) as f32 - 4.0;
+        draw_text(text, x, y, FONT_SIZE as f32, color);
+    }
+
+    fn visible_rows(&self) -> usize {
+        let height = screen_height() - PADDING * 2.0;
+        ((height / LINE_HEIGHT) as usize).saturating_sub(HEADER_LINES + FOOTER_LINES)
+    }
+
+    fn ensure_visible(&mut self, row: usize, total_rows: usize) {
+        let visible_rows = self.visible_rows();
+        if row < self.scroll_row {
+            self.scroll_row = row;
+        } else if row >= self.scroll_row + visible_rows {
+            self.scroll_row = (row + 1).saturating_sub(visible_rows);
+        }
+        self.scroll_row = self.scroll_row.min(total_rows.saturating_sub(visible_rows));
+        self.follow_pc = false;
+    }
+
+    // Convert a position relative to the panel into a memory address
+    fn byte_at(&self, x: f32, y: f32, ram_len: usize) -> Option<u16> {
+        let line = ((y - PADDING) / LINE_HEIGHT).floor();
+        if line < HEADER_LINES as f32 {
+            return None;
+        }
+        let row = line as usize - HEADER_LINES;
+        if row >= self.visible_rows() {
+            return None;
+        }
+        let row = self.scroll_row + row;
+
+        let col = ((x - PADDING) / char_width() - 6.0) / 3.0;
+        if col < 0.0 || col >= ROW_BYTES as f32 {
+            return None;
+        }
+
+        let addr = row * ROW_BYTES + col as usize;
+        (addr < ram_len).then_some(addr as u16)
+    }
+}
+
+// Write an lcov report for the ROM alongside the disassembly listing it refers to
+fn save_coverage(cpu: &Cpu, coverage: &Coverage, path: &Path) -> io::Result<()> {
+    let (start, end) = cpu.get_rom_bounds();
+
+    // Map back to assembler source when the symbols include line numbers
+    if let Some(symbols) = cpu.debugger().symbols()
+        && symbols.has_source_lines()
+    {
+        let lcov = coverage.lcov("chip8", cpu.get_ram(), start, end, |addr| {
+            symbols.source_line(addr).cloned()
+        });
+        return fs::write(path, lcov);
+    }
+
+    let listing_path = path.with_extension("lst");
+    fs::write(
+        &listing_path,
+        coverage.listing(cpu.get_ram(), cpu.get_variant(), start, end),
+    )?;
+
+    // Listing lines are numbered in address order
+    let listing_name = listing_path.display().to_string();
+    let lines: HashMap<u16, u32> = coverage
+        .instruction_addrs(start, end)
+        .into_iter()
+        .zip(1..)
+        .collect();
+    let lcov = coverage.lcov("chip8", cpu.get_ram(), start, end, |addr| {
+        lines.get(&addr).map(|line| (listing_name.clone(), *line))
+    });
+    fs::write(path, lcov)
+}
+
+// Log-scaled heatmap color for an execution count
+fn heat_color(count: u64, max: u64) -> Option<Color> {
+    if count == 0 || max == 0 {
+        return None;
+    }
+    let heat = ((count as f32).ln_1p() / (max as f32).ln_1p()).clamp(0.0, 1.0);
+    Some(Color::new(
+        0.3 + 0.6 * heat,
+        0.1 + 0.4 * heat,
+        0.0,
+        0.3 + 0.5 * heat,
+    ))
+}
+
+fn char_width() -> f32 {
+    measure_text("0", None, FONT_SIZE, 1.0).width
+}
@@ -1,15 +1,46 @@
 #![windows_subsystem = "windows"]
 
+mod debug_panel;
+mod speed;
+
+use chip8_emu_backend::compare::{Lockstep, ReferenceTrace};
 use chip8_emu_backend::*;
+use debug_panel::DebugPanel;
 use macroquad::prelude::*;
 use rfd::{FileDialog, MessageDialog, MessageLevel};
-use std::{fs::File, io::Read};
+use speed::Speed;
+use std::{fs, path::Path};
 
 // Scale window to accomodate for larger screens.
 const SCALE: i32 = 10;
 const WINDOW_WIDTH: i32 = (SCREEN_WIDTH as i32) * SCALE;
 const WINDOW_HEIGHT: i32 = (SCREEN_HEIGHT as i32) * SCALE;
 
+// Scale for a screen width, halved for the MegaChip screen to fit on ours
+fn window_scale(screen_width: usize) -> i32 {
+    if screen_width > HIRES_SCREEN_WIDTH {
+        SCALE / 2
+    } else {
+        SCALE
+    }
+}
+
+// Local ports the GDB and editor (DAP) servers listen on
+const GDB_PORT: u16 = 1234;
+const DAP_PORT: u16 = 4711;
+
+// Variants offered on the setup screen and the keys that pick them
+const VARIANTS: [(KeyCode, Chip8Variant); 8] = [
+    (KeyCode::Key1, Chip8Variant::Chip8),
+    (KeyCode::Key2, Chip8Variant::HiresChip8),
+    (KeyCode::Key3, Chip8Variant::Chip8X),
+    (KeyCode::Key4, Chip8Variant::Chip48),
+    (KeyCode::Key5, Chip8Variant::SuperChip10),
+    (KeyCode::Key6, Chip8Variant::SuperChip11),
+    (KeyCode::Key7, Chip8Variant::SuperChipModern),
+    (KeyCode::Key8, Chip8Variant::MegaChip),
+];
+
 const KEYS: [KeyCode; 16] = [
     KeyCode::X,    // 0
     KeyCode::Key1, // 1
@@ -29,6 +60,26 @@ const KEYS: [KeyCode; 16] = [
     KeyCode::V,    // F
 ];
 
+// CHIP-8X second keypad on the numpad
+const KEYS2: [KeyCode; 16] = [
+    KeyCode::Kp0,        // 0
+    KeyCode::Kp1,        // 1
+    KeyCode::Kp2,        // 2
+    KeyCode::Kp3,        // 3
+    KeyCode::Kp4,        // 4
+    KeyCode::Kp5,        // 5
+    KeyCode::Kp6,        // 6
+    KeyCode::Kp7,        // 7
+    KeyCode::Kp8,        // 8
+    KeyCode::Kp9,        // 9
+    KeyCode::KpDivide,   // A
+    KeyCode::KpMultiply, // B
+    KeyCode::KpSubtract, // C
+    KeyCode::KpAdd,      // D
+    KeyCode::KpEnter,    // E
+    KeyCode::KpDecimal,  // F
+];
+
 fn window_config() -> Conf {
     Conf {
         window_title: String::from("Chip-8 Emulator"),
@@ -42,65 +93,286 @@ fn draw_screen(cpu: &Cpu) {
     // Clear window and make background black
     clear_background(BLACK);
 
-    let (screen_buf, screen_width, _, _) = cpu.get_display();
+    // Upload the screen as a texture, the framebuffer can be finer than the
+    // resolution when LoRes is doubled
+    let framebuffer = cpu.get_framebuffer();
+    let rgba = cpu.get_rgba(&MONO_PALETTE);
+    let texture = Texture2D::from_rgba8(
+        framebuffer.get_width() as u16,
+        framebuffer.get_height() as u16,
+        &rgba,
+    );
+    texture.set_filter(FilterMode::Nearest);
+
+    // MegaChip can fade the whole screen
+    let alpha = cpu
+        .get_megachip()
+        .map_or(1.0, |megachip| megachip.get_screen_alpha() as f32 / 255.0);
+    let (width, height) = cpu.get_resolution();
+    let scale = window_scale(width) as f32;
+    draw_texture_ex(
+        &texture,
+        0.0,
+        0.0,
+        Color::new(1.0, 1.0, 1.0, alpha),
+        DrawTextureParams {
+            dest_size: Some(vec2(width as f32 * scale, height as f32 * scale)),
+            ..Default::default()
+        },
+    );
+}
+
+fn show_error(description: &str) {
+    MessageDialog::new()
+        .set_title("Error")
+        .set_description(description)
+        .set_level(MessageLevel::Error)
+        .show();
+}
+
+fn show_info(description: &str) {
+    MessageDialog::new()
+        .set_title("Info")
+        .set_description(description)
+        .set_level(MessageLevel::Info)
+        .show();
+}
+
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let buffer = fs::read(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    if buffer.is_empty() {
+        return Err(format!("{} is empty!", path.display()));
+    }
+    Ok(buffer)
+}
+
+// Resize the window to fit the game screen and, if open, the debug panel
+fn resize_window(screen_width: usize, screen_height: usize, show_panel: bool) {
+    let scale = window_scale(screen_width);
+    let mut width = screen_width as i32 * scale;
+    let mut height = screen_height as i32 * scale;
+    if show_panel {
+        width += debug_panel::PANEL_WIDTH;
+        height = height.max(debug_panel::PANEL_MIN_HEIGHT);
+    }
+    request_new_screen_size(width as f32, height as f32);
+}
+
+fn read_reference_trace(path: &Path) -> Result<ReferenceTrace, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let trace = ReferenceTrace::parse(&text)
+        .map_err(|e| format!("Invalid trace {}: {}", path.display(), e))?;
+    if trace.is_empty() {
+        return Err(format!("{} contains no instructions!", path.display()));
+    }
+    Ok(trace)
+}
+
+// Per-ROM settings shipped with the emulator, see the file for the format
+const ROM_DATABASE: &str = include_str!("../../assets/romdb.txt");
 
-    for (i, pixel) in screen_buf.iter().enumerate() {
-        if *pixel {
-            // Convert 1D array's index into 2D (x, y) position
-            let x = (i % screen_width) as i32;
-            let y = (i / screen_width) as i32;
+fn read_rom_database(path: &Path) -> Result<RomDatabase, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    RomDatabase::parse(&text).map_err(|e| format!("Invalid ROM database {}: {}", path.display(), e))
+}
 
-            // Draw rectangle at (x, y), scaled up by SCALE
-            draw_rectangle(
-                (x * SCALE) as f32,
-                (y * SCALE) as f32,
-                SCALE as f32,
-                SCALE as f32,
-                WHITE,
-            );
+// Settings the ROM database has for a ROM, from the built-in database and
+// a romdb.txt next to the ROM, which takes precedence
+fn rom_settings(path: &Path, rom_data: &[u8]) -> RomSettings {
+    let mut database = RomDatabase::parse(ROM_DATABASE).expect("Invalid embedded romdb.txt");
+    let local_path = path.with_file_name("romdb.txt");
+    if local_path.exists() {
+        match read_rom_database(&local_path) {
+            Ok(local) => database.extend(local),
+            Err(e) => show_error(&e),
         }
     }
+    database.lookup(rom_data).copied().unwrap_or_default()
 }
 
-async fn setup() -> Option<(Chip8Variant, Vec<u8>)> {
+// "unlimited" or a number of instructions per second
+fn read_ips(path: &Path) -> Result<Ips, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    Ips::parse(&text).ok_or_else(|| format!("Invalid speed in {}: {}", path.display(), text.trim()))
+}
+
+fn read_symbols(path: &Path) -> Result<Symbols, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    Symbols::parse(&text).map_err(|e| format!("Invalid symbols {}: {}", path.display(), e))
+}
+
+// Pause emulation and open the debug panel showing why
+fn break_into_debugger(cpu: &Cpu, debug_panel: &mut DebugPanel, reason: String) {
+    debug_panel.stop_reason = Some(reason);
+    if !debug_panel.visible {
+        debug_panel.visible = true;
+        let (w, h) = cpu.get_resolution();
+        resize_window(w, h, true);
+    }
+}
+
+// Tell attached GDB and editor clients that emulation stopped
+fn report_stop(
+    gdb_server: &mut Option<GdbServer>,
+    dap_server: &mut Option<DapServer>,
+    reason: Option<BreakReason>,
+) {
+    if let Some(server) = gdb_server {
+        server.report_stop(reason);
+    }
+    if let Some(server) = dap_server {
+        server.report_stop(reason);
+    }
+}
+
+// Options chosen on the setup screen
+struct Setup {
+    variant: Chip8Variant,
+    rom_data: Vec<u8>,
+    // Reference trace to run in lockstep with
+    reference: Option<ReferenceTrace>,
+    // Symbols from a .sym file next to the ROM
+    symbols: Option<Symbols>,
+    // CPU speed from a .ips file next to the ROM
+    ips: Option<Ips>,
+    // Variant, speed and quirks from the ROM database
+    settings: RomSettings,
+    // Wait for a GDB client before running
+    gdb: bool,
+    // Wait for an editor before running
+    dap: bool,
+    // Wrap sprites around the screen edges instead of clipping them
+    wrap: bool,
+}
+
+async fn setup() -> Option<Setup> {
     let mut variant: Option<Chip8Variant> = None;
+    let mut compare = false;
+    let mut gdb = false;
+    let mut dap = false;
+    let mut wrap = false;
 
     loop {
+        let toggle_color = |on: bool| if on { GREEN } else { WHITE };
+
         draw_text("Chip-8 Emulator", 155.9375, 50.0, 50.0, WHITE);
-        draw_text("Press [1] for Chip-8", 188.75, 100.0, 30.0, WHITE);
-        draw_text("Press [2] for SuperChip", 169.0625, 130.0, 30.0, WHITE);
-        draw_text("Press [Enter] to load ROM", 155.9375, 200.0, 30.0, YELLOW);
+        // Variants in two columns, the selected one in green
+        for (idx, (_, v)) in VARIANTS.iter().enumerate() {
+            let x = 40.0 + (idx % 2) as f32 * 300.0;
+            let y = 95.0 + (idx / 2) as f32 * 28.0;
+            let text = format!("[{}] {}", idx + 1, v);
+            draw_text(&text, x, y, 26.0, toggle_color(variant == Some(*v)));
+        }
+        draw_text(
+            "[T] Compare trace",
+            40.0,
+            215.0,
+            26.0,
+            toggle_color(compare),
+        );
+        draw_text("[G] GDB", 270.0, 215.0, 26.0, toggle_color(gdb));
+        draw_text("[D] Editor", 400.0, 215.0, 26.0, toggle_color(dap));
+        draw_text("[W] Wrap", 530.0, 215.0, 26.0, toggle_color(wrap));
+        draw_text("Press [Enter] to load ROM", 155.9375, 255.0, 30.0, YELLOW);
 
-        if let Some(v) = variant {
-            draw_text(&format!("{:?}", v), 40.0, 280.0, 30.0, GREEN);
+        let mut ports = Vec::new();
+        if gdb {
+            ports.push(format!("GDB on :{}", GDB_PORT));
+        }
+        if dap {
+            ports.push(format!("DAP on :{}", DAP_PORT));
         }
+        draw_text(&ports.join("   "), 40.0, 295.0, 26.0, GREEN);
 
-        if is_key_pressed(KeyCode::Key1) {
-            variant = Some(Chip8Variant::Chip8);
-        } else if is_key_pressed(KeyCode::Key2) {
-            variant = Some(Chip8Variant::SuperChip);
+        for (key, v) in VARIANTS {
+            if is_key_pressed(key) {
+                variant = Some(v);
+            }
+        }
+        if is_key_pressed(KeyCode::T) {
+            compare = !compare;
+        }
+        if is_key_pressed(KeyCode::G) {
+            gdb = !gdb;
+        }
+        if is_key_pressed(KeyCode::D) {
+            dap = !dap;
+        }
+        if is_key_pressed(KeyCode::W) {
+            wrap = !wrap;
         }
 
-        if is_key_pressed(KeyCode::Enter) {
-            if let Some(v) = variant {
-                let file = FileDialog::new()
-                    .add_filter("CHIP-8 ROM", &["ch8", "rom"])
-                    .add_filter("All Files", &["*"])
-                    .pick_file();
+        if is_key_pressed(KeyCode::Enter)
+            && let Some(v) = variant
+        {
+            let file = FileDialog::new()
+                .add_filter("CHIP-8 ROM", &["ch8", "rom", "mc8"])
+                .add_filter("All Files", &["*"])
+                .pick_file();
 
-                if let Some(path) = file {
-                    let mut rom = File::open(path).expect("Unable to open file");
-                    let mut buffer = Vec::new();
-                    rom.read_to_end(&mut buffer).unwrap();
-                    return Some((v, buffer));
+            if let Some(path) = file {
+                // Pick up symbols exported by the assembler alongside the ROM
+                let sym_path = path.with_extension("sym");
+                let symbols = if sym_path.exists() {
+                    read_symbols(&sym_path).map_err(|e| show_error(&e)).ok()
+                } else {
+                    None
+                };
+                // and the speed the ROM was designed for
+                let ips_path = path.with_extension("ips");
+                let ips = if ips_path.exists() {
+                    read_ips(&ips_path).map_err(|e| show_error(&e)).ok()
                 } else {
-                    MessageDialog::new()
-                        .set_title("Error")
-                        .set_description("No ROM selected!")
-                        .set_level(MessageLevel::Error)
-                        .show();
-                    return None;
+                    None
+                };
+
+                // Let the user pick another ROM if this one can't be read
+                match read_rom(&path) {
+                    Ok(rom_data) if !compare => {
+                        return Some(Setup {
+                            variant: v,
+                            settings: rom_settings(&path, &rom_data),
+                            rom_data,
+                            reference: None,
+                            symbols,
+                            ips,
+                            gdb,
+                            dap,
+                            wrap,
+                        });
+                    }
+                    Ok(rom_data) => {
+                        let trace = FileDialog::new()
+                            .add_filter("Trace", &["log", "txt"])
+                            .add_filter("All Files", &["*"])
+                            .pick_file();
+                        match trace.map(|path| read_reference_trace(&path)) {
+                            Some(Ok(reference)) => {
+                                return Some(Setup {
+                                    variant: v,
+                                    settings: rom_settings(&path, &rom_data),
+                                    rom_data,
+                                    reference: Some(reference),
+                                    symbols,
+                                    ips,
+                                    gdb,
+                                    dap,
+                                    wrap,
+                                });
+                            }
+                            Some(Err(e)) => show_error(&e),
+                            None => show_error("No reference trace selected!"),
+                        }
+                    }
+                    Err(e) => show_error(&e),
                 }
+            } else {
+                show_error("No ROM selected!");
+                return None;
             }
         }
 
@@ -110,7 +382,18 @@ async fn setup() -> Option<(Chip8Variant, Vec<u8>)> {
 
 #[macroquad::main(window_config)]
 async fn main() {
-    let Some((variant, rom_data)) = setup().await else {
+    let Some(Setup {
+        variant,
+        rom_data,
+        reference,
+        symbols,
+        ips,
+        settings,
+        gdb,
+        dap,
+        wrap,
+    }) = setup().await
+    else {
         return;
     };
 
@@ -118,38 +401,195 @@ async fn main() {
 
     let audio = AudioManager::new().await;
 
+    // The ROM database knows better than the setup screen, but the wrap
+    // toggle and a .ips file are the user's choice for this run
+    let variant = settings.variant.unwrap_or(variant);
     let mut chip8 = Cpu::new(audio, variant);
+    settings.apply_quirks(chip8.quirks_mut());
+    // Every variant clips, but some ROMs were written for wrapping
+    if wrap {
+        chip8.quirks_mut().clipping = false;
+    }
 
-    chip8.load(&rom_data);
+    if let Err(e) = chip8.load(&rom_data) {
+        show_error(&format!("Unable to load ROM: {}", e));
+        return;
+    }
+    chip8.debugger_mut().set_symbols(symbols);
 
-    // Initalize prev_res to the default resolution (lores)
+    // Initalize prev_res to the default resolution (lores), which isn't
+    // 64x32 for every variant
     let mut prev_res = DisplayMode::LoRes;
+    let (w, h) = chip8.get_resolution();
+    resize_window(w, h, false);
+
+    let mut debug_panel = DebugPanel::new();
+    let ips = ips.or(settings.ips).unwrap_or(Ips::default_for(variant));
+    let mut speed = Speed::new(ips);
+    let mut lockstep = reference.map(Lockstep::new);
+
+    // Start paused while waiting for GDB or an editor, so breakpoints can be
+    // set first
+    let mut gdb_server = if gdb {
+        match GdbServer::bind(("127.0.0.1", GDB_PORT)) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                show_error(&format!("Unable to start GDB server: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut dap_server = if dap {
+        match DapServer::bind(("127.0.0.1", DAP_PORT)) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                show_error(&format!("Unable to start DAP server: {}", e));
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let mut paused = gdb_server.is_some() || dap_server.is_some();
+    if paused {
+        debug_panel.stop_reason = Some("waiting for a debugger to attach".to_string());
+    }
 
     'gameloop: loop {
-        if is_quit_requested() || is_key_pressed(KeyCode::Escape) {
+        // Escape closes the debug prompt rather than quitting while typing
+        let typing = debug_panel.is_typing();
+        if is_quit_requested() || (!typing && is_key_pressed(KeyCode::Escape)) {
             break 'gameloop;
         }
+        // Keys typed into the debug prompt don't reach the game
         for (key, &keycode) in KEYS.iter().enumerate() {
-            let pressed = is_key_down(keycode);
+            let pressed = !typing && is_key_down(keycode);
             chip8.keypress(key, pressed);
         }
+        for (key, &keycode) in KEYS2.iter().enumerate() {
+            chip8.keypress2(key, !typing && is_key_down(keycode));
+        }
+
+        // [F1] toggles the debug panel, [P] pauses emulation, see Speed for
+        // frame advance, fast-forward and slow motion
+        let (w, _) = chip8.get_resolution();
+        if is_key_pressed(KeyCode::F1) {
+            debug_panel.visible = !debug_panel.visible;
+            let (w, h) = chip8.get_resolution();
+            resize_window(w, h, debug_panel.visible);
+        }
+        if !typing && is_key_pressed(KeyCode::P) {
+            paused = !paused;
+            debug_panel.stop_reason = None;
+            if paused {
+                report_stop(&mut gdb_server, &mut dap_server, None);
+            }
+        }
 
-        let (_, w, h, display_mode) = chip8.get_display();
+        // Attached GDB and editor clients can stop and resume emulation
+        if let Some(server) = &mut gdb_server {
+            match server.poll(&mut chip8) {
+                Some(GdbEvent::Halt) => {
+                    paused = true;
+                    debug_panel.stop_reason = Some("stopped by GDB".to_string());
+                }
+                // Run on without the client, whose breakpoints are gone
+                Some(GdbEvent::Resume | GdbEvent::Detach) => {
+                    paused = false;
+                    debug_panel.stop_reason = None;
+                }
+                None => {}
+            }
+        }
+        if let Some(server) = &mut dap_server {
+            match server.poll(&mut chip8) {
+                Some(DapEvent::Halt) => {
+                    paused = true;
+                    debug_panel.stop_reason = Some("stopped by editor".to_string());
+                }
+                Some(DapEvent::Resume | DapEvent::Detach) => {
+                    paused = false;
+                    debug_panel.stop_reason = None;
+                }
+                None => {}
+            }
+        }
+        debug_panel.update(&mut chip8, (w as i32 * window_scale(w)) as f32, paused);
+        speed.update(typing);
 
-        let ticks_per_frame = config::ticks_per_frame(variant);
+        // Run the emulated frames owed for the real time since the last
+        // rendered frame, independent of the display's refresh rate
+        let deadline = speed.deadline();
+        for _ in 0..speed.frames(paused) {
+            speed.start_frame(&chip8);
+            while speed.next_tick(&chip8) {
+                if get_time() > deadline {
+                    break;
+                }
+                // Halted on a stack error, already reported below
+                if chip8.error().is_some() {
+                    break;
+                }
+                if let Some(lock) = &mut lockstep {
+                    // Check against the reference trace before each instruction
+                    if let Err(divergence) = lock.step(&mut chip8) {
+                        show_error(&divergence.to_string());
+                        let reason = format!("trace diverged at step {}", divergence.step);
+                        break_into_debugger(&chip8, &mut debug_panel, reason);
+                        report_stop(&mut gdb_server, &mut dap_server, None);
+                        paused = true;
+                        lockstep = None;
+                        break;
+                    }
+                    if lock.is_finished() {
+                        show_info(&format!(
+                            "Reference trace matched ({} instructions)",
+                            lock.position()
+                        ));
+                        lockstep = None;
+                    }
+                } else {
+                    chip8.tick();
+                }
+
+                if let Some(error) = chip8.error() {
+                    show_error(&error.to_string());
+                    break_into_debugger(&chip8, &mut debug_panel, error.to_string());
+                    report_stop(&mut gdb_server, &mut dap_server, Some(error.into()));
+                    paused = true;
+                    break;
+                }
+                // Pause and show the debug panel on breakpoints and watchpoints
+                if let Some(reason) = chip8.take_break() {
+                    break_into_debugger(&chip8, &mut debug_panel, reason.to_string());
+                    report_stop(&mut gdb_server, &mut dap_server, Some(reason));
+                    paused = true;
+                    break;
+                }
+            }
+            chip8.tick_timers();
 
-        for _ in 0..ticks_per_frame {
-            chip8.tick();
+            // Stop fast-forwarding once something paused emulation
+            if paused {
+                break;
+            }
         }
-        chip8.tick_timers();
 
         // Update display size when changing from LoRes to HiRes (and vice versa)
+        let display_mode = chip8.get_display_mode();
+        let (w, h) = chip8.get_resolution();
         if display_mode != prev_res {
-            request_new_screen_size((w as i32 * SCALE) as f32, (h as i32 * SCALE) as f32);
+            resize_window(w, h, debug_panel.visible);
             prev_res = display_mode;
         }
 
         draw_screen(&chip8);
+        speed.draw(paused);
+        if debug_panel.visible {
+            debug_panel.draw(&chip8, (w as i32 * window_scale(w)) as f32, paused);
+        }
 
         next_frame().await;
     }
@@ -0,0 +1,228 @@
+use chip8_emu_backend::config::FRAME_RATE;
+use chip8_emu_backend::timing::{VIP_AVAILABLE_CYCLES, VIP_FRAME_CYCLES};
+use chip8_emu_backend::{Cpu, Ips};
+use macroquad::prelude::*;
+
+// Fast-forward multipliers, cycled with [-] and [=]
+const FAST_FORWARD: [u32; 4] = [2, 4, 8, 16];
+// Speed multiplier in slow motion
+const SLOW_MOTION: f64 = 0.25;
+// Most emulated frames (at 1x) we catch up on after a stall, anything
+// beyond that is dropped rather than run in one burst
+const MAX_CATCH_UP: f64 = 6.0;
+// CPU speeds stepped through with [[] and []], above the last is unlimited
+const IPS_STEPS: [u32; 12] = [
+    100, 200, 300, 500, 700, 1000, 1500, 2000, 3000, 5000, 10000, 20000,
+];
+// Share of each rendered frame spent emulating at unlimited speed
+const UNLIMITED_FRAME_SHARE: f64 = 0.75;
+// Seconds the IPS overlay stays up after changing speed
+const IPS_OVERLAY_TIME: f64 = 2.0;
+
+const FONT_SIZE: f32 = 24.0;
+
+// Fixed timestep scheduler. Accumulates real time and decides how many
+// emulated frames (one tick_timers call each) run per rendered frame, so the
+// timers run at FRAME_RATE whatever the display's refresh rate is, and how
+// many instructions run in each of them.
+//
+// [N] advances one frame while paused, holding [Tab] fast-forwards, [L]
+// toggles slow motion, [[] / []] change the instructions per second and [\]
+// toggles COSMAC VIP timing.
+pub struct Speed {
+    ips: Ips,
+    fast_forward_idx: usize,
+    fast_forward: bool,
+    slow_motion: bool,
+    frame_advance: bool,
+    // Emulated frames owed for the real time that has passed
+    budget: f64,
+    // Fractional instructions carried over to the next frame
+    tick_budget: f64,
+    // Instructions left in the current emulated frame
+    frame_ticks: usize,
+    // CPU cycle count the current emulated frame ends at, in VIP timing
+    frame_end: u64,
+    // Speed to go back to when leaving VIP timing
+    ips_before_vip: Ips,
+    // Time the IPS last changed, to show the overlay
+    ips_changed_at: Option<f64>,
+}
+
+impl Speed {
+    pub fn new(ips: Ips) -> Self {
+        Self {
+            ips,
+            fast_forward_idx: 1,
+            fast_forward: false,
+            slow_motion: false,
+            frame_advance: false,
+            budget: 0.0,
+            tick_budget: 0.0,
+            frame_ticks: 0,
+            frame_end: 0,
+            ips_before_vip: ips,
+            ips_changed_at: None,
+        }
+    }
+
+    pub fn set_ips(&mut self, ips: Ips) {
+        self.ips = ips;
+        self.tick_budget = 0.0;
+        self.ips_changed_at = Some(get_time());
+    }
+
+    // Handle the speed keys, ignored while typing into the debug panel
+    pub fn update(&mut self, typing: bool) {
+        self.fast_forward = !typing && is_key_down(KeyCode::Tab);
+        if typing {
+            return;
+        }
+        if is_key_pressed(KeyCode::N) {
+            self.frame_advance = true;
+        }
+        if is_key_pressed(KeyCode::L) {
+            self.slow_motion = !self.slow_motion;
+        }
+        if is_key_pressed(KeyCode::Minus) {
+            self.fast_forward_idx = self.fast_forward_idx.saturating_sub(1);
+        }
+        if is_key_pressed(KeyCode::Equal) {
+            self.fast_forward_idx = (self.fast_forward_idx + 1).min(FAST_FORWARD.len() - 1);
+        }
+
+        if is_key_pressed(KeyCode::Backslash) {
+            match self.ips {
+                Ips::CosmacVip => self.set_ips(self.ips_before_vip),
+                ips => {
+                    self.ips_before_vip = ips;
+                    self.set_ips(Ips::CosmacVip);
+                }
+            }
+        }
+        if is_key_pressed(KeyCode::LeftBracket) {
+            let slower = match self.ips {
+                Ips::Limited(ips) => IPS_STEPS.iter().rev().find(|step| **step < ips),
+                Ips::Unlimited => IPS_STEPS.last(),
+                Ips::CosmacVip => None,
+            };
+            if let Some(ips) = slower {
+                self.set_ips(Ips::Limited(*ips));
+            }
+        }
+        if is_key_pressed(KeyCode::RightBracket)
+            && let Ips::Limited(ips) = self.ips
+        {
+            match IPS_STEPS.iter().find(|step| **step > ips) {
+                Some(faster) => self.set_ips(Ips::Limited(*faster)),
+                None => self.set_ips(Ips::Unlimited),
+            }
+        }
+    }
+
+    // Number of emulated frames to run for the time since the last
+    // rendered frame
+    pub fn frames(&mut self, paused: bool) -> u32 {
+        let advance = std::mem::take(&mut self.frame_advance);
+        if paused {
+            // Don't make up for the time spent paused
+            self.budget = 0.0;
+            return advance as u32;
+        }
+
+        let rate = if self.fast_forward {
+            FAST_FORWARD[self.fast_forward_idx] as f64
+        } else if self.slow_motion {
+            SLOW_MOTION
+        } else {
+            1.0
+        };
+        self.budget += get_frame_time() as f64 * FRAME_RATE as f64 * rate;
+        self.budget = self.budget.min(MAX_CATCH_UP * rate);
+
+        let frames = self.budget.floor();
+        self.budget -= frames;
+        frames as u32
+    }
+
+    // Set up the instruction budget of the next emulated frame
+    pub fn start_frame(&mut self, cpu: &Cpu) {
+        match self.ips {
+            Ips::Limited(ips) => {
+                self.tick_budget += ips as f64 / FRAME_RATE as f64;
+                let ticks = self.tick_budget.floor();
+                self.tick_budget -= ticks;
+                self.frame_ticks = ticks as usize;
+            }
+            // The caller stops at the deadline instead
+            Ips::Unlimited => self.frame_ticks = usize::MAX,
+            // Carry over cycles the last instruction of the previous frame
+            // ran past its end, but not a backlog from stepping while paused.
+            // A frame that ended early waiting for vblank loses the rest of
+            // its cycles, like the VIP waiting for the interrupt.
+            Ips::CosmacVip => {
+                let cycles = cpu.get_cycles();
+                let start = self
+                    .frame_end
+                    .min(cycles)
+                    .max(cycles.saturating_sub(VIP_FRAME_CYCLES));
+                self.frame_end = start + VIP_AVAILABLE_CYCLES;
+            }
+        }
+    }
+
+    // Whether another instruction fits in the current emulated frame. A draw
+    // waiting for vblank ends the frame early, as nothing runs until then.
+    pub fn next_tick(&mut self, cpu: &Cpu) -> bool {
+        if cpu.is_waiting_for_vblank() {
+            return false;
+        }
+        match self.ips {
+            Ips::CosmacVip => cpu.get_cycles() < self.frame_end,
+            _ if self.frame_ticks > 0 => {
+                self.frame_ticks -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Time to stop emulating this rendered frame, leaving time to draw
+    pub fn deadline(&self) -> f64 {
+        match self.ips {
+            Ips::Limited(_) | Ips::CosmacVip => f64::INFINITY,
+            Ips::Unlimited => get_time() + get_frame_time() as f64 * UNLIMITED_FRAME_SHARE,
+        }
+    }
+
+    // Indicators in the top left corner of the game screen
+    pub fn draw(&self, paused: bool) {
+        let mut lines = Vec::new();
+        if paused {
+            lines.push("PAUSED [P] resume [N] next frame".to_string());
+        } else if self.fast_forward {
+            lines.push(format!(">> {}x", FAST_FORWARD[self.fast_forward_idx]));
+        } else if self.slow_motion {
+            lines.push(format!("SLOW {}x", SLOW_MOTION));
+        }
+        if self
+            .ips_changed_at
+            .is_some_and(|changed| get_time() - changed < IPS_OVERLAY_TIME)
+        {
+            lines.push(self.ips.to_string());
+        }
+
+        for (idx, text) in lines.iter().enumerate() {
+            let size = measure_text(text, None, FONT_SIZE as u16, 1.0);
+            let y = 4.0 + idx as f32 * FONT_SIZE;
+            draw_rectangle(
+                4.0,
+                y,
+                size.width + 8.0,
+                FONT_SIZE,
+                Color::new(0.0, 0.0, 0.0, 0.6),
+            );
+            draw_text(text, 8.0, y + size.offset_y, FONT_SIZE, YELLOW);
+        }
+    }
+}